@@ -87,6 +87,8 @@ fn generate_particles(width: f64, height: f64, r: f64, density: f64, seed: u64)
                 r,
                 collisions_count: 0,
                 color: None,
+                restitution: None,
+                fixed: false,
             });
             y += step;
         }
@@ -95,8 +97,8 @@ fn generate_particles(width: f64, height: f64, r: f64, density: f64, seed: u64)
     particles
 }
 
-fn save_particles(particles: &Vec<Particle>, file_name: &str) {
+fn save_scene(simulation: &Simulation, file_name: &str) {
     let mut file = File::create(file_name).unwrap();
-    let data = serde_json::to_string(particles).unwrap();
+    let data = simulation.to_scene().to_json();
     file.write_all(&data.as_bytes());
 }
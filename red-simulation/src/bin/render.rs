@@ -0,0 +1,99 @@
+// Renders the scene without a browser:
+//
+//   render <scene.json> <output> [--fps 30] [--duration 5] [--scale 1]
+//
+// Output ending with `.gif` is an animated GIF, ending with `.png`
+// is an animated PNG, anything else is a directory for PNG frames.
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use std::process;
+
+use red_simulation::render::{render_frames, write_apng, write_gif, RasterRenderer};
+use red_simulation::scene::Scene;
+use red_simulation::simulation::Simulation;
+
+struct Args {
+    scene: String,
+    output: String,
+    fps: u32,
+    duration: f64,
+    scale: f64,
+}
+
+const USAGE: &str = "usage: render <scene.json> <output> [--fps 30] [--duration 5] [--scale 1]";
+
+fn parse_args() -> Result<Args, String> {
+    let mut positional = Vec::new();
+    let mut args = Args {
+        scene: String::new(),
+        output: String::new(),
+        fps: 30,
+        duration: 5.,
+        scale: 1.,
+    };
+
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg);
+            continue;
+        }
+        let value = iter
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        let invalid = || format!("invalid value of {}: {}", arg, value);
+        match arg.as_str() {
+            "--fps" => args.fps = value.parse().map_err(|_| invalid())?,
+            "--duration" => args.duration = value.parse().map_err(|_| invalid())?,
+            "--scale" => args.scale = value.parse().map_err(|_| invalid())?,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    if positional.len() != 2 {
+        return Err(USAGE.to_owned());
+    }
+    let positive = |value: f64| value > 0. && value.is_finite();
+    if args.fps == 0 || !positive(args.duration) || !positive(args.scale) {
+        return Err("fps, duration and scale must be positive".to_owned());
+    }
+    args.output = positional.pop().unwrap();
+    args.scene = positional.pop().unwrap();
+    Ok(args)
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let scene = Scene::from_json(&fs::read_to_string(&args.scene)?)?;
+    let mut sim = Simulation::from_scene(&scene)?;
+    let mut renderer = RasterRenderer::for_simulation(&sim, args.scale);
+
+    let count = (args.duration * args.fps as f64).round().max(1.) as usize;
+    let frames = render_frames(&mut sim, &mut renderer, args.fps, count);
+
+    let output = Path::new(&args.output);
+    match output.extension().and_then(|ext| ext.to_str()) {
+        Some("gif") => write_gif(BufWriter::new(File::create(output)?), &frames, args.fps)?,
+        Some("png") => write_apng(BufWriter::new(File::create(output)?), &frames, args.fps)?,
+        _ => {
+            fs::create_dir_all(output)?;
+            for (i, frame) in frames.iter().enumerate() {
+                let path = output.join(format!("frame_{:05}.png", i));
+                frame.write_png(BufWriter::new(File::create(path)?))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
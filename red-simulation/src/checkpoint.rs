@@ -0,0 +1,134 @@
+use std::convert::TryInto;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collisions::{CollisionEvent, CollisionPair};
+use crate::geom::{Segment, Vec2};
+use crate::grid::Grid;
+use crate::particle::{Particle, ParticleId, ParticleStore};
+use crate::pressure::WallMonitor;
+use crate::random::Random;
+use crate::simulation::DrawParams;
+
+// Version of the checkpoint format. Checkpoints are meant to be
+// restored by the same build, so any change of the simulation's
+// state must increase it.
+pub const CHECKPOINT_VERSION: u32 = 4;
+
+// Full state of the simulation, that is enough to continue it
+// bit-for-bit. Observers and javascript callbacks are not included.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    // Must stay the first field, it's read before the rest of the binary
+    pub(crate) version: u32,
+    pub(crate) w: f64,
+    pub(crate) h: f64,
+    pub(crate) initialized: bool,
+    pub(crate) segments: Vec<Segment>,
+    pub(crate) moving_segments: Vec<usize>,
+    pub(crate) particles: ParticleStore,
+    // Internal array of the event heap, it keeps
+    // the processing order of simultaneous events
+    pub(crate) events: Vec<CollisionEvent>,
+    pub(crate) collisions_happend: Vec<CollisionPair>,
+    pub(crate) grid: Grid,
+    pub(crate) t: f64,
+    pub(crate) ticks_per_sec: u32,
+    pub(crate) tick_time: f64,
+    pub(crate) gravity: Vec2,
+    pub(crate) periodic_x: bool,
+    pub(crate) periodic_y: bool,
+    pub(crate) restitution: f64,
+    pub(crate) collapse_time: f64,
+    // JSON has no infinity, so particles that
+    // have never collided are stored as nulls
+    pub(crate) last_collisions: Vec<Option<f64>>,
+    pub(crate) exact_events: bool,
+    pub(crate) wall_work: f64,
+    pub(crate) walls: WallMonitor,
+    pub(crate) random: Random,
+    pub(crate) game: Option<GameCheckpoint>,
+    pub(crate) scene_player: Option<Particle>,
+    pub(crate) draw_params: DrawParams,
+}
+
+// Game parameters without the javascript callback.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameCheckpoint {
+    pub p_particle: ParticleId,
+    pub player_uuid: String,
+    pub player_name: String,
+    pub game_started_tick: f64,
+    pub game_ended: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckpointError {
+    // Data is corrupted or doesn't match the format
+    Decode(String),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Decode(reason) => write!(f, "can't decode the checkpoint: {}", reason),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "checkpoint version {} is not supported, expected {}",
+                version, CHECKPOINT_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+impl Checkpoint {
+    // Simulation time of the checkpoint
+    pub fn t(&self) -> f64 {
+        self.t
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Checkpoint, CheckpointError> {
+        // Version is checked first, the rest of
+        // the layout may differ between versions
+        let version = data
+            .get(..4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or_else(|| CheckpointError::Decode("data is too short".to_owned()))?;
+        check_version(version)?;
+
+        bincode::deserialize(data).map_err(|e| CheckpointError::Decode(e.to_string()))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<Checkpoint, CheckpointError> {
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+
+        let Version { version } =
+            serde_json::from_str(json).map_err(|e| CheckpointError::Decode(e.to_string()))?;
+        check_version(version)?;
+
+        serde_json::from_str(json).map_err(|e| CheckpointError::Decode(e.to_string()))
+    }
+}
+
+fn check_version(version: u32) -> Result<(), CheckpointError> {
+    if version == CHECKPOINT_VERSION {
+        Ok(())
+    } else {
+        Err(CheckpointError::UnsupportedVersion(version))
+    }
+}
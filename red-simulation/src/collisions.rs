@@ -2,9 +2,15 @@ use std::cmp::Ordering;
 use std::convert::Into;
 use std::hash::{Hash, Hasher};
 
+use serde::{Deserialize, Serialize};
+
+use crate::particle::ParticleId;
+
 // Particle vs Particle
 pub mod pvp {
+    use crate::geom::Vec2;
     use crate::particle::Particle;
+    use crate::poly;
 
     pub fn time_to_hit(left: &Particle, right: &Particle) -> Option<f64> {
         if left == right {
@@ -41,6 +47,36 @@ pub mod pvp {
         }
     }
 
+    // Same as `time_to_hit`, but particles move with constant accelerations,
+    // `da` is the acceleration of the `left` relative to the `right`.
+    // Distance between parabolic trajectories is a quartic polynomial.
+    pub fn time_to_hit_accelerated(left: &Particle, right: &Particle, da: Vec2) -> Option<f64> {
+        if da.is_zero() {
+            return time_to_hit(left, right);
+        }
+        if left == right {
+            return None;
+        }
+
+        let dr = left.pos - right.pos;
+        let dv = left.v - right.v;
+        let a = da * 0.5;
+        let sigma = left.r + right.r;
+
+        if dr.len() < sigma {
+            return Some(0.);
+        }
+
+        // |dr + dv * t + a * t^2|^2 - sigma^2
+        poly::first_descending_root(&[
+            dr * dr - sigma * sigma,
+            2. * (dr * dv),
+            dv * dv + 2. * (a * dr),
+            2. * (a * dv),
+            a * a,
+        ])
+    }
+
     pub fn is_collision(left: &Particle, right: &Particle) -> bool {
         if left == right {
             return false;
@@ -49,22 +85,38 @@ pub mod pvp {
     }
 
     pub fn collision(left: &Particle, right: &Particle) -> (Particle, Particle) {
+        inelastic_collision(left, right, 1.)
+    }
+
+    // Collision with the coefficient of restitution `e`,
+    // where 1 is perfectly elastic and 0 is perfectly plastic.
+    // Momentum is conserved unless one of particles is fixed,
+    // the fixed one works as a wall and keeps its collisions count.
+    pub fn inelastic_collision(left: &Particle, right: &Particle, e: f64) -> (Particle, Particle) {
+        let mut new_left = left.clone();
+        let mut new_right = right.clone();
+
+        let inv_m = left.inv_m() + right.inv_m();
+        if inv_m == 0. {
+            return (new_left, new_right);
+        }
+
         let dr = right.pos - left.pos;
         let dv = right.v - left.v;
         let dv_dr = dv * dr;
         let dist_sqr = dr.len_sqr();
 
-        let j_norm = 2. * left.m * right.m * dv_dr / (left.m + right.m);
+        let j_norm = (1. + e) * dv_dr / inv_m;
         let j = dr * (j_norm / dist_sqr);
 
-        let mut new_left = left.clone();
-        let mut new_right = right.clone();
-
-        new_left.v += j * (1. / left.m);
-        new_right.v -= j * (1. / right.m);
-
-        new_left.collisions_count += 1;
-        new_right.collisions_count += 1;
+        if !left.fixed {
+            new_left.v += j * left.inv_m();
+            new_left.collisions_count += 1;
+        }
+        if !right.fixed {
+            new_right.v -= j * right.inv_m();
+            new_right.collisions_count += 1;
+        }
 
         (new_left, new_right)
     }
@@ -72,10 +124,23 @@ pub mod pvp {
 
 // Particle vs Segment
 pub mod pvs {
-    use crate::geom::{LCIntersection, Line, Segment};
+    use crate::geom::{LCIntersection, Line, Segment, Vec2};
     use crate::particle::Particle;
+    use crate::poly;
+    use crate::random::Random;
+
+    // Particle's state in the frame of reference of the segment,
+    // where the moving segment stays still.
+    #[inline]
+    fn in_segment_frame(left: &Particle, right: &Segment) -> Particle {
+        Particle {
+            v: left.v - right.velocity,
+            ..*left
+        }
+    }
 
     pub fn time_to_hit(left: &Particle, right: &Segment) -> Option<f64> {
+        let left = &in_segment_frame(left, right);
         if left.v.is_zero() {
             return None;
         }
@@ -118,6 +183,43 @@ pub mod pvs {
         None
     }
 
+    // Same as `time_to_hit`, but the particle moves with the constant
+    // acceleration `a`. Parabola may pass the segment's line outside
+    // of the segment and come back from the other side, so both
+    // sides are checked.
+    pub fn time_to_hit_accelerated(left: &Particle, right: &Segment, a: Vec2) -> Option<f64> {
+        if a.is_zero() {
+            return time_to_hit(left, right);
+        }
+        let left = &in_segment_frame(left, right);
+
+        // Signed distance to the line: s0 + vn * t + an * t^2
+        let s0 = (left.pos - right.p1) * right.n;
+        let vn = left.v * right.n;
+        let an = 0.5 * (a * right.n);
+
+        let contact = |t: f64| {
+            let pos = left.pos + left.v * t + a * (0.5 * t * t);
+            right.contains_point(&(pos - right.n * ((pos - right.p1) * right.n)))
+        };
+
+        // Particle's own side is checked first, it may already touch the line.
+        let side = if s0 < 0. { -1. } else { 1. };
+        let near = poly::first_descending_root(&[side * s0 - left.r, side * vn, side * an]);
+
+        // The opposite side can be reached only by crossing the line.
+        let far = poly::real_roots(&[-side * s0 - left.r, -side * vn, -side * an])
+            .into_iter()
+            .find(|&t| t > 0. && -side * (vn + 2. * an * t) < 0.);
+
+        near.into_iter()
+            .chain(far)
+            .filter(|&t| contact(t))
+            .fold(None, |acc: Option<f64>, t| {
+                Some(acc.map_or(t, |m| m.min(t)))
+            })
+    }
+
     pub fn is_collision(left: &Particle, right: &Segment) -> bool {
         match right.line.intersect_circle(&left.circle()) {
             LCIntersection::OnePoint(p) => right.contains_point(&p),
@@ -129,40 +231,97 @@ pub mod pvs {
     }
 
     pub fn collision(left: &Particle, right: &Segment) -> Particle {
+        inelastic_collision(left, right, 1.)
+    }
+
+    // Reflection that keeps only `e` part of the normal velocity
+    // relative to the segment. Moving segment changes particle's energy.
+    pub fn inelastic_collision(left: &Particle, right: &Segment, e: f64) -> Particle {
         let mut new_left = left.clone();
-        new_left.v = new_left.v - right.n * (new_left.v * right.n * 2.);
+        let vn = (left.v - right.velocity) * right.n;
+        new_left.v -= right.n * (vn * (1. + e));
+        new_left.collisions_count += 1;
+        new_left
+    }
+
+    // Bounce off the wall kept at the temperature `t`, the incoming
+    // velocity is forgotten. Relative to the segment the particle leaves
+    // with the velocity of the gas particle, that crosses the wall at
+    // this temperature: the normal component has the density
+    // m*v/t * exp(-m*v^2/2t), the tangential one is normal with the
+    // variance t/m.
+    pub fn thermal_collision(
+        left: &Particle,
+        right: &Segment,
+        t: f64,
+        random: &mut Random,
+    ) -> Particle {
+        let mut new_left = *left;
+        let outer_n = if (left.pos - right.p1) * right.n < 0. {
+            right.n * -1.
+        } else {
+            right.n
+        };
+        let sigma = (t / left.m).sqrt();
+        let vn = sigma * (-2. * random.uniform().ln()).sqrt();
+        let vt = sigma * random.normal();
+        new_left.v = right.velocity + outer_n * vn + right.v * vt;
         new_left.collisions_count += 1;
         new_left
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum Collision {
     ParticleVsParticle {
-        // Indexes of particles
-        p1: usize,
-        p2: usize,
+        // Handles of particles
+        p1: ParticleId,
+        p2: ParticleId,
         // cc - Collisions count
         p1_cc: u64,
         p2_cc: u64,
     },
     ParticleVsSegment {
-        p: usize,
+        p: ParticleId,
         s: usize,
         p_cc: u64,
     },
+    // Particle's center leaves its grid cell
+    CellCrossing {
+        p: ParticleId,
+        // Index of the cell particle enters
+        cell: usize,
+        p_cc: u64,
+    },
+    // Particle's center leaves the domain through the periodic
+    // border and appears on the opposite side
+    BoundaryCrossing {
+        p: ParticleId,
+        // Index of the cell particle enters
+        cell: usize,
+        p_cc: u64,
+    },
+    // Particle's center crosses the gate, see `probes::Gate`
+    GateCrossing {
+        p: ParticleId,
+        gate: usize,
+        p_cc: u64,
+    },
 }
 
 impl Into<CollisionPair> for Collision {
     fn into(self) -> CollisionPair {
         match self {
-            Self::ParticleVsParticle { p1, p2, .. } => CollisionPair::PvP(p1, p2),
-            Self::ParticleVsSegment { p, s, .. } => CollisionPair::PvE(p, s),
+            Self::ParticleVsParticle { p1, p2, .. } => CollisionPair::PvP(p1.index(), p2.index()),
+            Self::ParticleVsSegment { p, s, .. } => CollisionPair::PvE(p.index(), s),
+            Self::CellCrossing { p, cell, .. } => CollisionPair::PvC(p.index(), cell),
+            Self::BoundaryCrossing { p, cell, .. } => CollisionPair::PvC(p.index(), cell),
+            Self::GateCrossing { p, gate, .. } => CollisionPair::PvG(p.index(), gate),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct CollisionEvent {
     pub t: f64,
     pub collision: Collision,
@@ -193,10 +352,12 @@ impl PartialOrd for CollisionEvent {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum CollisionPair {
     PvP(usize, usize),
     PvE(usize, usize),
+    PvC(usize, usize),
+    PvG(usize, usize),
 }
 
 impl Hash for CollisionPair {
@@ -217,6 +378,16 @@ impl Hash for CollisionPair {
                 p.hash(state);
                 s.hash(state);
             }
+            Self::PvC(p, c) => {
+                state.write_u8(2);
+                p.hash(state);
+                c.hash(state);
+            }
+            Self::PvG(p, g) => {
+                state.write_u8(3);
+                p.hash(state);
+                g.hash(state);
+            }
         };
     }
 }
@@ -228,6 +399,8 @@ impl PartialEq for CollisionPair {
                 (p1 == p3 && p2 == p4) || (p1 == p4 && p2 == p3)
             }
             (Self::PvE(p1, p2), Self::PvE(p3, p4)) => p1 == p3 && p2 == p4,
+            (Self::PvC(p1, c1), Self::PvC(p2, c2)) => p1 == p2 && c1 == c2,
+            (Self::PvG(p1, g1), Self::PvG(p2, g2)) => p1 == p2 && g1 == g2,
             _ => false,
         }
     }
@@ -241,6 +414,7 @@ mod tests {
     use crate::compare_floats;
     use crate::geom::{Segment, Vec2};
     use crate::particle::Particle;
+    use crate::random::Random;
 
     fn particle(pos: Vec2, v: Vec2, m: f64, r: f64) -> Particle {
         Particle {
@@ -250,6 +424,8 @@ mod tests {
             r,
             collisions_count: 0,
             color: None,
+            restitution: None,
+            fixed: false,
         }
     }
 
@@ -367,4 +543,159 @@ mod tests {
         // Summary speed must not change
         compare_floats!(p_new.v.len(), p_1.v.len());
     }
+
+    #[test]
+    fn test_particle_v_particle_inelastic_collision() {
+        let p_1 = particle(Vec2 { x: -1.0, y: 0.0 }, Vec2 { x: 3.0, y: 1.0 }, 1.0, 1.0);
+        let p_2 = particle(Vec2 { x: 1.0, y: 0.0 }, Vec2 { x: -1.0, y: 0.0 }, 3.0, 1.0);
+
+        let (pn_1, pn_2) = pvp::inelastic_collision(&p_1, &p_2, 0.5);
+
+        // Momentum is conserved
+        let momentum = p_1.v * p_1.m + p_2.v * p_2.m;
+        compare_floats!((pn_1.v * pn_1.m + pn_2.v * pn_2.m - momentum).len(), 0.);
+
+        // Normal relative velocity is reversed and halved
+        compare_floats!(pn_2.v.x - pn_1.v.x, 0.5 * (p_1.v.x - p_2.v.x));
+        compare_floats!(pn_1.v.y, 1.0);
+        compare_floats!(pn_2.v.y, 0.0);
+
+        // Perfectly plastic collision
+        let (pn_1, pn_2) = pvp::inelastic_collision(&p_1, &p_2, 0.);
+        compare_floats!(pn_1.v.x, pn_2.v.x);
+    }
+
+    #[test]
+    fn test_particle_v_segment_inelastic_collision() {
+        let p_1 = particle(Vec2 { x: 2.0, y: 1.0 }, Vec2 { x: 1.0, y: -4.0 }, 1.0, 1.0);
+        let seg = Segment::from_points(Vec2 { x: 0.0, y: 0.0 }, Vec2 { x: 5.0, y: 0.0 });
+
+        let p_new = pvs::inelastic_collision(&p_1, &seg, 0.25);
+
+        compare_floats!(p_new.v.x, 1.0);
+        compare_floats!(p_new.v.y, 1.0);
+    }
+
+    #[test]
+    fn test_particle_v_particle_time_to_hit_accelerated() {
+        let p_1 = particle(Vec2 { x: 10.0, y: 0.0 }, Vec2 { x: 0.0, y: 0.0 }, 1.0, 1.0);
+        let p_2 = particle(Vec2 { x: 0.0, y: 0.0 }, Vec2 { x: 0.0, y: 1.0 }, 1.0, 1.0);
+
+        // Relative motion is linear under the same acceleration
+        let da = Vec2 { x: 0.0, y: 0.0 };
+        assert_eq!(pvp::time_to_hit_accelerated(&p_1, &p_2, da), None);
+
+        // 10 - t^2 = 2
+        let da = Vec2 { x: -2.0, y: 0.0 };
+        let p_2 = particle(Vec2 { x: 0.0, y: 0.0 }, Vec2 { x: 0.0, y: 0.0 }, 1.0, 1.0);
+        compare_floats!(
+            pvp::time_to_hit_accelerated(&p_1, &p_2, da).unwrap(),
+            8f64.sqrt()
+        );
+
+        // Thrown away, but comes back
+        let p_1 = particle(Vec2 { x: 10.0, y: 0.0 }, Vec2 { x: 4.0, y: 0.0 }, 1.0, 1.0);
+        compare_floats!(
+            pvp::time_to_hit_accelerated(&p_1, &p_2, da).unwrap(),
+            2. + 12f64.sqrt()
+        );
+    }
+
+    #[test]
+    fn test_particle_v_segment_time_to_hit_accelerated() {
+        let seg = Segment::from_points(Vec2 { x: 0.0, y: 10.0 }, Vec2 { x: 10.0, y: 10.0 });
+        let gravity = Vec2 { x: 0.0, y: 2.0 };
+
+        // Free fall from the height 9 - 1
+        let p_1 = particle(Vec2 { x: 5.0, y: 1.0 }, Vec2 { x: 0.0, y: 0.0 }, 1.0, 1.0);
+        compare_floats!(
+            pvs::time_to_hit_accelerated(&p_1, &seg, gravity).unwrap(),
+            8f64.sqrt()
+        );
+
+        // Thrown up past the segment's end, hits it from above
+        let p_1 = particle(
+            Vec2 { x: 12.0, y: 14.0 },
+            Vec2 { x: -1.0, y: -8.0 },
+            1.0,
+            1.0,
+        );
+        let t = pvs::time_to_hit_accelerated(&p_1, &seg, gravity).unwrap();
+        compare_floats!(14.0 - 8.0 * t + t * t, 9.0);
+        assert!(t > 4.0);
+
+        // Flies over the segment
+        let p_1 = particle(Vec2 { x: 5.0, y: 1.0 }, Vec2 { x: 20.0, y: 0.0 }, 1.0, 1.0);
+        assert_eq!(pvs::time_to_hit_accelerated(&p_1, &seg, gravity), None);
+    }
+
+    #[test]
+    fn test_particle_v_fixed_particle_collision() {
+        let p_1 = particle(Vec2 { x: 0.0, y: 0.0 }, Vec2 { x: 3.0, y: 4.0 }, 1.0, 1.0);
+        let p_2 = particle(Vec2 { x: 2.0, y: 0.0 }, Vec2 { x: 0.0, y: 0.0 }, 1.0, 1.0).as_fixed();
+
+        let (p_1_new, p_2_new) = pvp::collision(&p_1, &p_2);
+        compare_floats!(p_1_new.v.x, -3.0);
+        compare_floats!(p_1_new.v.y, 4.0);
+        assert_eq!(p_2_new, p_2);
+        assert_eq!(p_1_new.collisions_count, 1);
+
+        let (p_2_new, p_1_new) = pvp::inelastic_collision(&p_2, &p_1, 0.5);
+        compare_floats!(p_1_new.v.x, -1.5);
+        compare_floats!(p_1_new.v.y, 4.0);
+        assert_eq!(p_2_new, p_2);
+    }
+
+    #[test]
+    fn test_particle_v_moving_segment() {
+        let seg = Segment::from_points(Vec2 { x: 10.0, y: 0.0 }, Vec2 { x: 10.0, y: 20.0 })
+            .with_velocity(-2.0, 0.0);
+
+        let p_1 = particle(Vec2 { x: 5.0, y: 10.0 }, Vec2 { x: 0.0, y: 0.0 }, 1.0, 1.0);
+        compare_floats!(pvs::time_to_hit(&p_1, &seg).unwrap(), 2.0);
+
+        // Particle runs away slower than the segment moves
+        let p_2 = particle(Vec2 { x: 5.0, y: 10.0 }, Vec2 { x: -1.0, y: 0.0 }, 1.0, 1.0);
+        compare_floats!(pvs::time_to_hit(&p_2, &seg).unwrap(), 4.0);
+
+        // Segment passes its velocity to the particle twice
+        let p_1_new = pvs::collision(&p_1, &seg);
+        compare_floats!(p_1_new.v.x, -4.0);
+        compare_floats!(p_1_new.v.y, 0.0);
+
+        let p_2_new = pvs::inelastic_collision(&p_2, &seg, 0.5);
+        compare_floats!(p_2_new.v.x, -2.5);
+    }
+
+    #[test]
+    fn test_particle_v_thermal_segment() {
+        let mut random = Random::new(1);
+        let seg = Segment::from_points(Vec2 { x: 10.0, y: 0.0 }, Vec2 { x: 10.0, y: 20.0 })
+            .with_velocity(0.0, 1.0);
+        let p_1 = particle(
+            Vec2 { x: 11.0, y: 10.0 },
+            Vec2 { x: -3.0, y: 0.0 },
+            2.0,
+            1.0,
+        );
+
+        // Moments of the flux distribution at the temperature 4
+        let n = 100000;
+        let (mut vn_sqr, mut vt, mut vt_sqr) = (0.0, 0.0, 0.0);
+        for _ in 0..n {
+            let p_new = pvs::thermal_collision(&p_1, &seg, 4.0, &mut random);
+            assert!(p_new.v.x > 0.0);
+            assert_eq!(p_new.collisions_count, 1);
+            vn_sqr += p_new.v.x * p_new.v.x;
+            vt += p_new.v.y - 1.0;
+            vt_sqr += (p_new.v.y - 1.0).powi(2);
+        }
+        assert!((vn_sqr / n as f64 - 4.0).abs() < 0.05);
+        assert!((vt / n as f64).abs() < 0.02);
+        assert!((vt_sqr / n as f64 - 2.0).abs() < 0.05);
+
+        // The particle on the other side leaves to the left
+        let p_2 = particle(Vec2 { x: 9.0, y: 10.0 }, Vec2 { x: 3.0, y: 0.0 }, 2.0, 1.0);
+        assert!(pvs::thermal_collision(&p_2, &seg, 4.0, &mut random).v.x < 0.0);
+    }
 }
@@ -0,0 +1,272 @@
+use serde::{Deserialize, Serialize};
+
+use crate::geom::Vec2;
+use crate::particle::{Particle, ParticleId, RGBA};
+
+// Mean squared displacement of the particles of the same colour.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpeciesMsd {
+    pub color: Option<RGBA>,
+    // Number of particles tracked since the reference moment
+    pub count: usize,
+    pub msd: Vec<f64>,
+}
+
+// Mean squared displacement of the moving particles
+// since the reference moment `t0`, sampled regularly.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MsdCurve {
+    pub t0: f64,
+    // Time passed since `t0` for every sample
+    pub t: Vec<f64>,
+    pub msd: Vec<f64>,
+    pub species: Vec<SpeciesMsd>,
+}
+
+impl MsdCurve {
+    // See `diffusion_coefficient`.
+    pub fn diffusion_coefficient(&self, from: f64) -> Option<f64> {
+        diffusion_coefficient(&self.t, &self.msd, from)
+    }
+
+    pub fn species_diffusion_coefficient(&self, color: Option<RGBA>, from: f64) -> Option<f64> {
+        self.species
+            .iter()
+            .find(|species| species.color == color)
+            .and_then(|species| diffusion_coefficient(&self.t, &species.msd, from))
+    }
+}
+
+// Diffusion coefficient D of 2D motion, that is the slope of the line
+// MSD = 4 * D * t + c fitted by least squares. Only samples with
+// `t >= from` are used, so the ballistic motion at short times can be
+// skipped. There must be at least two such samples.
+pub fn diffusion_coefficient(t: &[f64], msd: &[f64], from: f64) -> Option<f64> {
+    let points: Vec<(f64, f64)> = t
+        .iter()
+        .copied()
+        .zip(msd.iter().copied())
+        .filter(|(t, _)| *t >= from)
+        .collect();
+    let n = points.len() as f64;
+    let mean_t = points.iter().map(|(t, _)| t).sum::<f64>() / n;
+    let mean_msd = points.iter().map(|(_, msd)| msd).sum::<f64>() / n;
+
+    let (cov, var) = points.iter().fold((0., 0.), |(cov, var), (t, msd)| {
+        let dt = t - mean_t;
+        (cov + dt * (msd - mean_msd), var + dt * dt)
+    });
+    if points.len() < 2 || var == 0. {
+        None
+    } else {
+        Some(cov / var / 4.)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Tracked {
+    id: ParticleId,
+    origin: Vec2,
+    // Sum of the jumps over periodic borders taken
+    // back, so `pos + offset` is the unwrapped position
+    offset: Vec2,
+    species: usize,
+}
+
+// Tracks the displacement of the particles, that are moving at
+// the reference moment. Particles added later are not tracked.
+#[derive(Debug, Clone)]
+pub(crate) struct MsdTracker {
+    interval: f64,
+    samples: u64,
+    pub(crate) active: bool,
+    // Indexed by slots, like in the simulation
+    particles: Vec<Option<Tracked>>,
+    pub(crate) curve: MsdCurve,
+}
+
+impl MsdTracker {
+    pub(crate) fn new<'a, I>(t0: f64, interval: f64, particles: I) -> MsdTracker
+    where
+        I: Iterator<Item = (ParticleId, &'a Particle)>,
+    {
+        let mut tracker = MsdTracker {
+            interval,
+            samples: 0,
+            active: true,
+            particles: Vec::new(),
+            curve: MsdCurve {
+                t0,
+                t: Vec::new(),
+                msd: Vec::new(),
+                species: Vec::new(),
+            },
+        };
+        for (id, p) in particles.filter(|(_, p)| !p.fixed) {
+            let species = tracker.species(p.color);
+            tracker.curve.species[species].count += 1;
+            if tracker.particles.len() <= id.index() {
+                tracker.particles.resize(id.index() + 1, None);
+            }
+            tracker.particles[id.index()] = Some(Tracked {
+                id,
+                origin: p.pos,
+                offset: Vec2::default(),
+                species,
+            });
+        }
+        tracker
+    }
+
+    pub(crate) fn interval(&self) -> f64 {
+        self.interval
+    }
+
+    // Moment of the next sample, if it's due not later than `t`.
+    pub(crate) fn next_sample(&self, t: f64) -> Option<f64> {
+        let next = self.curve.t0 + self.samples as f64 * self.interval;
+        if self.active && next <= t {
+            Some(next)
+        } else {
+            None
+        }
+    }
+
+    // Appends the sample at the moment `t`, `particles`
+    // are the current positions of all the particles.
+    pub(crate) fn sample<I: Iterator<Item = (ParticleId, Vec2)>>(&mut self, t: f64, particles: I) {
+        let (msd, species) = self.measure(particles);
+        self.curve.t.push(t - self.curve.t0);
+        self.curve.msd.push(msd);
+        for (curve, msd) in self.curve.species.iter_mut().zip(species) {
+            curve.msd.push(msd);
+        }
+        self.samples += 1;
+    }
+
+    // Mean squared displacement of all the tracked particles and of every
+    // species. Removed particles don't count, so the mean of the empty
+    // group is zero.
+    pub(crate) fn measure<I: Iterator<Item = (ParticleId, Vec2)>>(
+        &self,
+        particles: I,
+    ) -> (f64, Vec<f64>) {
+        let mut sums = vec![(0., 0usize); self.curve.species.len()];
+        for (id, pos) in particles {
+            if let Some(tracked) = self.tracked(id) {
+                let sum = &mut sums[tracked.species];
+                sum.0 += (pos + tracked.offset - tracked.origin).len_sqr();
+                sum.1 += 1;
+            }
+        }
+
+        let mean = |(sum, count): (f64, usize)| if count > 0 { sum / count as f64 } else { 0. };
+        let total = sums
+            .iter()
+            .fold((0., 0), |acc, sum| (acc.0 + sum.0, acc.1 + sum.1));
+        (mean(total), sums.into_iter().map(mean).collect())
+    }
+
+    // The particle has jumped by `shift` over the periodic border.
+    pub(crate) fn wrapped(&mut self, id: ParticleId, shift: Vec2) {
+        if let Some(Some(tracked)) = self.particles.get_mut(id.index()) {
+            if tracked.id == id {
+                tracked.offset -= shift;
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, id: ParticleId) {
+        if self.tracked(id).is_some() {
+            self.particles[id.index()] = None;
+        }
+    }
+
+    fn tracked(&self, id: ParticleId) -> Option<&Tracked> {
+        match self.particles.get(id.index()) {
+            Some(Some(tracked)) if tracked.id == id => Some(tracked),
+            _ => None,
+        }
+    }
+
+    fn species(&mut self, color: Option<RGBA>) -> usize {
+        let species = &mut self.curve.species;
+        species
+            .iter()
+            .position(|species| species.color == color)
+            .unwrap_or_else(|| {
+                species.push(SpeciesMsd {
+                    color,
+                    count: 0,
+                    msd: Vec::new(),
+                });
+                species.len() - 1
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compare_floats;
+
+    #[test]
+    fn test_diffusion_coefficient() {
+        let t = [0., 1., 2., 3., 4.];
+        compare_floats!(
+            diffusion_coefficient(&t, &[0., 1., 8., 12., 16.], 2.).unwrap(),
+            1.
+        );
+        assert_eq!(diffusion_coefficient(&t, &[0.; 5], 4.), None);
+    }
+
+    #[test]
+    fn test_msd_tracker() {
+        let red = Some(RGBA::new(255, 0, 0, None));
+        let particles = [
+            (
+                ParticleId::new(0, 0),
+                Particle::new(1., 1., 0., 0., 1., 1., red),
+            ),
+            (
+                ParticleId::new(1, 0),
+                Particle::new(5., 5., 0., 0., 1., 1., None),
+            ),
+            (
+                ParticleId::new(2, 0),
+                Particle::new(9., 1., 0., 0., 1., 1., red),
+            ),
+            (
+                ParticleId::new(3, 0),
+                Particle::new(5., 9., 0., 0., 1., 1., None).as_fixed(),
+            ),
+        ];
+        let mut tracker = MsdTracker::new(2., 0.5, particles.iter().map(|(id, p)| (*id, p)));
+        assert_eq!(tracker.next_sample(1.9), None);
+        assert_eq!(tracker.next_sample(3.), Some(2.));
+
+        // The first particle has crossed the periodic border at x = 0
+        tracker.wrapped(particles[0].0, Vec2 { x: 10., y: 0. });
+        let moved = vec![
+            (particles[0].0, Vec2 { x: 9., y: 1. }),
+            (particles[1].0, Vec2 { x: 5., y: 8. }),
+            (particles[2].0, Vec2 { x: 9., y: 5. }),
+            (particles[3].0, Vec2 { x: 5., y: 4. }),
+            // Particle added later isn't tracked
+            (ParticleId::new(5, 0), Vec2 { x: 0., y: 0. }),
+        ];
+        tracker.sample(2.5, moved.clone().into_iter());
+        tracker.remove(particles[2].0);
+        tracker.sample(3., moved.into_iter());
+
+        let curve = &tracker.curve;
+        assert_eq!(curve.t, vec![0.5, 1.]);
+        assert_eq!(curve.msd, vec![(4. + 9. + 16.) / 3., (4. + 9.) / 2.]);
+        assert_eq!(curve.species.len(), 2);
+        assert_eq!(curve.species[0].color, red);
+        assert_eq!(curve.species[0].count, 2);
+        assert_eq!(curve.species[0].msd, vec![10., 4.]);
+        assert_eq!(curve.species[1].msd, vec![9., 9.]);
+        assert_eq!(tracker.next_sample(3.), Some(3.));
+    }
+}
@@ -0,0 +1,210 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::particle::Particle;
+
+// Histogram with equal bins over the range [min, max).
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    min: f64,
+    max: f64,
+    counts: Vec<u32>,
+    // Values that are out of the range
+    underflow: u32,
+    overflow: u32,
+}
+
+#[wasm_bindgen]
+impl Histogram {
+    pub fn get_min(&self) -> f64 {
+        self.min
+    }
+
+    pub fn get_max(&self) -> f64 {
+        self.max
+    }
+
+    pub fn get_bin_width(&self) -> f64 {
+        (self.max - self.min) / self.counts.len() as f64
+    }
+
+    pub fn get_counts(&self) -> Vec<u32> {
+        self.counts.clone()
+    }
+
+    pub fn get_underflow(&self) -> u32 {
+        self.underflow
+    }
+
+    pub fn get_overflow(&self) -> u32 {
+        self.overflow
+    }
+
+    // Number of values including the ones out of the range.
+    pub fn get_total(&self) -> u32 {
+        self.counts.iter().sum::<u32>() + self.underflow + self.overflow
+    }
+
+    pub fn get_centers(&self) -> Vec<f64> {
+        let width = self.get_bin_width();
+        (0..self.counts.len())
+            .map(|i| self.min + width * (i as f64 + 0.5))
+            .collect()
+    }
+
+    // Probability density estimate, it's directly
+    // comparable with the theoretical distribution.
+    pub fn get_density(&self) -> Vec<f64> {
+        let norm = self.get_total() as f64 * self.get_bin_width();
+        self.counts
+            .iter()
+            .map(|&count| if norm > 0. { count as f64 / norm } else { 0. })
+            .collect()
+    }
+}
+
+impl Histogram {
+    // There is at least one bin.
+    pub fn new(min: f64, max: f64, bins: usize) -> Histogram {
+        Histogram {
+            min,
+            max,
+            counts: vec![0; bins.max(1)],
+            underflow: 0,
+            overflow: 0,
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        if value < self.min {
+            self.underflow += 1;
+        } else if value >= self.max {
+            self.overflow += 1;
+        } else {
+            let bin = ((value - self.min) / self.get_bin_width()) as usize;
+            // Rounding may put the value right below `max` out of the last bin
+            let last = self.counts.len() - 1;
+            self.counts[bin.min(last)] += 1;
+        }
+    }
+
+    pub fn counts(&self) -> &[u32] {
+        &self.counts
+    }
+}
+
+// Density of the speed distribution of 2D ideal gas
+// particles with mass `m` at the temperature `t`.
+pub fn maxwell_speed_pdf(v: f64, m: f64, t: f64) -> f64 {
+    if v < 0. || t <= 0. {
+        return 0.;
+    }
+    m * v / t * (-m * v * v / (2. * t)).exp()
+}
+
+// Density of the single velocity component distribution.
+pub fn maxwell_component_pdf(v: f64, m: f64, t: f64) -> f64 {
+    if t <= 0. {
+        return 0.;
+    }
+    (m / (2. * PI * t)).sqrt() * (-m * v * v / (2. * t)).exp()
+}
+
+// Equilibrium distributions of the gas with particles of different masses,
+// that is the mix of distributions for every mass weighted by its share.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Maxwell {
+    // Distinct masses together with their shares
+    masses: Vec<(f64, f64)>,
+    t: f64,
+}
+
+impl Maxwell {
+    pub(crate) fn new<'a, I: Iterator<Item = &'a Particle>>(particles: I, t: f64) -> Maxwell {
+        let mut masses: Vec<f64> = particles.filter(|p| !p.fixed).map(|p| p.m).collect();
+        masses.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let total = masses.len() as f64;
+        let mut shares: Vec<(f64, f64)> = Vec::new();
+        for m in masses {
+            match shares.last_mut() {
+                Some((last, share)) if *last == m => *share += 1. / total,
+                _ => shares.push((m, 1. / total)),
+            }
+        }
+        Maxwell { masses: shares, t }
+    }
+
+    pub(crate) fn speed_pdf(&self, v: f64) -> f64 {
+        self.mix(|m| maxwell_speed_pdf(v, m, self.t))
+    }
+
+    pub(crate) fn component_pdf(&self, v: f64) -> f64 {
+        self.mix(|m| maxwell_component_pdf(v, m, self.t))
+    }
+
+    fn mix<F: Fn(f64) -> f64>(&self, pdf: F) -> f64 {
+        self.masses.iter().map(|&(m, share)| share * pdf(m)).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compare_floats;
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::new(0., 2., 4);
+        for &value in &[-1., 0., 0.3, 0.5, 1.2, 1.99, 2., 5.] {
+            histogram.add(value);
+        }
+        assert_eq!(histogram.counts(), &[2, 1, 1, 1]);
+        assert_eq!(histogram.get_underflow(), 1);
+        assert_eq!(histogram.get_overflow(), 2);
+        assert_eq!(histogram.get_total(), 8);
+        assert_eq!(histogram.get_centers(), vec![0.25, 0.75, 1.25, 1.75]);
+        assert_eq!(histogram.get_density(), vec![0.5, 0.25, 0.25, 0.25]);
+    }
+
+    #[test]
+    fn test_maxwell() {
+        // Densities are normalized and the mean energy is equal to the temperature
+        let (m, t, dv) = (2., 3., 1e-3);
+        let speeds = (0..20000).map(|i| (i as f64 + 0.5) * dv);
+        let norm: f64 = speeds
+            .clone()
+            .map(|v| maxwell_speed_pdf(v, m, t) * dv)
+            .sum();
+        let energy: f64 = speeds
+            .map(|v| 0.5 * m * v * v * maxwell_speed_pdf(v, m, t) * dv)
+            .sum();
+        assert!((norm - 1.).abs() < 1e-6, "{}", norm);
+        assert!((energy - t).abs() < 1e-6, "{}", energy);
+
+        let components = (-10000..10000).map(|i| (i as f64 + 0.5) * dv);
+        let norm: f64 = components
+            .map(|v| maxwell_component_pdf(v, m, t) * dv)
+            .sum();
+        assert!((norm - 1.).abs() < 1e-6, "{}", norm);
+
+        let particles = [
+            Particle::new(0., 0., 0., 0., 1., 1., None),
+            Particle::new(0., 0., 0., 0., 3., 1., None),
+            Particle::new(0., 0., 0., 0., 1., 1., None),
+            Particle::new(0., 0., 0., 0., 7., 1., None).as_fixed(),
+        ];
+        let maxwell = Maxwell::new(particles.iter(), t);
+        compare_floats!(
+            maxwell.speed_pdf(1.5),
+            (2. * maxwell_speed_pdf(1.5, 1., t) + maxwell_speed_pdf(1.5, 3., t)) / 3.
+        );
+        compare_floats!(
+            maxwell.component_pdf(-0.5),
+            (2. * maxwell_component_pdf(-0.5, 1., t) + maxwell_component_pdf(-0.5, 3., t)) / 3.
+        );
+    }
+}
@@ -0,0 +1,420 @@
+use std::convert::TryInto;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::geom::{Segment, Vec2};
+use crate::observer::CollisionReport;
+use crate::particle::{Particle, ParticleId};
+use crate::scene::Scene;
+use crate::utils::to_js_value;
+
+// Version of the event log format, it's increased
+// on every incompatible change of the format.
+pub const EVENT_LOG_VERSION: u32 = 2;
+
+// Everything that changes particles' motion besides the free flight.
+// Externally tagged, since internally tagged enums can't be read by bincode.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LoggedEvent {
+    ParticleVsParticle {
+        t: f64,
+        p1: ParticleId,
+        p2: ParticleId,
+        // Velocities after the collision
+        v1: Vec2,
+        v2: Vec2,
+    },
+    ParticleVsSegment {
+        t: f64,
+        p: ParticleId,
+        s: usize,
+        v: Vec2,
+    },
+    ParticleAdded {
+        t: f64,
+        id: ParticleId,
+        particle: Particle,
+    },
+    ParticleRemoved {
+        t: f64,
+        id: ParticleId,
+    },
+    // Player's particle is moved by hand
+    ParticleMoved {
+        t: f64,
+        id: ParticleId,
+        pos: Vec2,
+    },
+    SegmentAdded {
+        t: f64,
+        segment: Segment,
+    },
+    SegmentVelocityChanged {
+        t: f64,
+        s: usize,
+        velocity: Vec2,
+    },
+    GravityChanged {
+        t: f64,
+        gravity: Vec2,
+    },
+}
+
+impl LoggedEvent {
+    pub fn t(&self) -> f64 {
+        match self {
+            Self::ParticleVsParticle { t, .. } => *t,
+            Self::ParticleVsSegment { t, .. } => *t,
+            Self::ParticleAdded { t, .. } => *t,
+            Self::ParticleRemoved { t, .. } => *t,
+            Self::ParticleMoved { t, .. } => *t,
+            Self::SegmentAdded { t, .. } => *t,
+            Self::SegmentVelocityChanged { t, .. } => *t,
+            Self::GravityChanged { t, .. } => *t,
+        }
+    }
+}
+
+impl From<&CollisionReport> for LoggedEvent {
+    fn from(report: &CollisionReport) -> Self {
+        match *report {
+            CollisionReport::ParticleVsParticle { t, p1, p2, .. } => {
+                LoggedEvent::ParticleVsParticle {
+                    t,
+                    p1: p1.id,
+                    p2: p2.id,
+                    v1: p1.v_after,
+                    v2: p2.v_after,
+                }
+            }
+            CollisionReport::ParticleVsSegment { t, p, s, .. } => LoggedEvent::ParticleVsSegment {
+                t,
+                p: p.id,
+                s,
+                v: p.v_after,
+            },
+        }
+    }
+}
+
+// Processed events together with the state they are applied to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventLog {
+    // Must stay the first field, it's read before the rest of the binary
+    pub version: u32,
+    // Moment when the log was started
+    pub t0: f64,
+    // State of the simulation at `t0`, the player's
+    // particle is added by the first event in game mode
+    pub scene: Scene,
+    // Handles of the scene's particles in the simulation
+    pub ids: Vec<ParticleId>,
+    pub events: Vec<LoggedEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventLogError {
+    // Data is corrupted or doesn't match the format
+    Decode(String),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for EventLogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Decode(reason) => write!(f, "can't decode the event log: {}", reason),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "event log version {} is not supported, expected {}",
+                version, EVENT_LOG_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EventLogError {}
+
+impl EventLog {
+    pub fn new(t0: f64, scene: Scene, ids: Vec<ParticleId>) -> EventLog {
+        EventLog {
+            version: EVENT_LOG_VERSION,
+            t0,
+            scene,
+            ids,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, event: LoggedEvent) {
+        self.events.push(event);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<EventLog, EventLogError> {
+        let version = data
+            .get(..4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or_else(|| EventLogError::Decode("data is too short".to_owned()))?;
+        check_version(version)?;
+
+        bincode::deserialize(data).map_err(|e| EventLogError::Decode(e.to_string()))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<EventLog, EventLogError> {
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+
+        let Version { version } =
+            serde_json::from_str(json).map_err(|e| EventLogError::Decode(e.to_string()))?;
+        check_version(version)?;
+
+        serde_json::from_str(json).map_err(|e| EventLogError::Decode(e.to_string()))
+    }
+}
+
+fn check_version(version: u32) -> Result<(), EventLogError> {
+    if version == EVENT_LOG_VERSION {
+        Ok(())
+    } else {
+        Err(EventLogError::UnsupportedVersion(version))
+    }
+}
+
+// Object together with the moment its state belongs to.
+#[derive(Debug, Clone, Copy)]
+struct Tracked<T> {
+    value: T,
+    t: f64,
+}
+
+// Reconstructs the system at any moment from the event log. Collisions
+// are not predicted, particles just fly between the logged events.
+#[wasm_bindgen]
+pub struct Replayer {
+    log: EventLog,
+    t: f64,
+    // Index of the next event to apply
+    next: usize,
+    gravity: Vec2,
+    // Indexed by slots, like in the simulation
+    particles: Vec<Option<(ParticleId, Tracked<Particle>)>>,
+    segments: Vec<Tracked<Segment>>,
+}
+
+#[wasm_bindgen]
+impl Replayer {
+    #[wasm_bindgen(js_name = from_json)]
+    pub fn from_json_js(json: &str) -> Result<Replayer, JsValue> {
+        EventLog::from_json(json)
+            .map(Replayer::new)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    pub fn get_t(&self) -> f64 {
+        self.t
+    }
+
+    // Moves the system to the moment `t`, going back is allowed.
+    // Events that happen exactly at `t` are not applied yet,
+    // the same way the trajectory recorder sees them.
+    pub fn seek(&mut self, t: f64) {
+        if t < self.t {
+            self.reset();
+        }
+        while let Some(event) = self.log.events.get(self.next).copied() {
+            if event.t() >= t {
+                break;
+            }
+            self.apply(&event);
+            self.next += 1;
+        }
+        self.t = t.max(self.log.t0);
+    }
+
+    pub fn get_particles(&self) -> JsValue {
+        let particles: Vec<Particle> = self.particles().into_iter().map(|(_, p)| p).collect();
+        to_js_value(&particles)
+    }
+}
+
+impl Replayer {
+    pub fn new(log: EventLog) -> Replayer {
+        let mut replayer = Replayer {
+            log,
+            t: 0.,
+            next: 0,
+            gravity: Vec2::default(),
+            particles: Vec::new(),
+            segments: Vec::new(),
+        };
+        replayer.reset();
+        replayer
+    }
+
+    pub fn log(&self) -> &EventLog {
+        &self.log
+    }
+
+    // Living particles at the current moment.
+    pub fn particles(&self) -> Vec<(ParticleId, Particle)> {
+        self.particles
+            .iter()
+            .flatten()
+            .map(|(id, tracked)| (*id, self.particle_at(tracked, self.t)))
+            .collect()
+    }
+
+    pub fn particle(&self, id: ParticleId) -> Option<Particle> {
+        self.tracked(id)
+            .map(|tracked| self.particle_at(tracked, self.t))
+    }
+
+    // All the segments including the domain borders at the current moment.
+    pub fn segments(&self) -> Vec<Segment> {
+        self.segments
+            .iter()
+            .map(|tracked| segment_at(tracked, self.t))
+            .collect()
+    }
+
+    fn reset(&mut self) {
+        let log = &self.log;
+        let scene = &log.scene;
+
+        self.t = log.t0;
+        self.next = 0;
+        self.gravity = scene.gravity;
+        self.segments =
+            Segment::create_rectangle_domain(Vec2::default(), scene.width, scene.height)
+                .into_iter()
+                .chain(scene.segments.iter().map(Segment::from))
+                .map(|segment| Tracked {
+                    value: segment,
+                    t: log.t0,
+                })
+                .collect();
+        let t0 = log.t0;
+        let initial: Vec<_> = log
+            .ids
+            .iter()
+            .copied()
+            .zip(scene.particles.clone())
+            .collect();
+        self.particles.clear();
+        for (id, particle) in initial {
+            self.insert(id, particle, t0);
+        }
+    }
+
+    fn apply(&mut self, event: &LoggedEvent) {
+        match *event {
+            LoggedEvent::ParticleVsParticle { t, p1, p2, v1, v2 } => {
+                self.set_velocity(p1, v1, t);
+                self.set_velocity(p2, v2, t);
+            }
+            LoggedEvent::ParticleVsSegment { t, p, v, .. } => self.set_velocity(p, v, t),
+            LoggedEvent::ParticleAdded { t, id, particle } => self.insert(id, particle, t),
+            LoggedEvent::ParticleRemoved { id, .. } => {
+                if self.tracked(id).is_some() {
+                    self.particles[id.index()] = None;
+                }
+            }
+            LoggedEvent::ParticleMoved { t, id, pos } => {
+                if self.tracked(id).is_some() {
+                    let mut moved = self.moved(id, t);
+                    moved.value.pos = pos;
+                    self.particles[id.index()] = Some((id, moved));
+                }
+            }
+            LoggedEvent::SegmentAdded { t, segment } => {
+                self.segments.push(Tracked { value: segment, t })
+            }
+            LoggedEvent::SegmentVelocityChanged { t, s, velocity } => {
+                if let Some(tracked) = self.segments.get(s) {
+                    let segment = segment_at(tracked, t);
+                    self.segments[s] = Tracked {
+                        value: segment.with_velocity(velocity.x, velocity.y),
+                        t,
+                    };
+                }
+            }
+            LoggedEvent::GravityChanged { t, gravity } => {
+                // Particles have to fly under the old gravity up to now
+                let ids: Vec<ParticleId> =
+                    self.particles.iter().flatten().map(|(id, _)| *id).collect();
+                for id in ids {
+                    let moved = self.moved(id, t);
+                    self.particles[id.index()] = Some((id, moved));
+                }
+                self.gravity = gravity;
+            }
+        }
+    }
+
+    fn insert(&mut self, id: ParticleId, particle: Particle, t: f64) {
+        if self.particles.len() <= id.index() {
+            self.particles.resize(id.index() + 1, None);
+        }
+        self.particles[id.index()] = Some((id, Tracked { value: particle, t }));
+    }
+
+    fn set_velocity(&mut self, id: ParticleId, v: Vec2, t: f64) {
+        if self.tracked(id).is_some() {
+            let mut moved = self.moved(id, t);
+            moved.value.v = v;
+            self.particles[id.index()] = Some((id, moved));
+        }
+    }
+
+    // Particle `id` moved to the moment `t`, the caller
+    // must be sure that the particle exists.
+    fn moved(&self, id: ParticleId, t: f64) -> Tracked<Particle> {
+        Tracked {
+            value: self.particle_at(self.tracked(id).unwrap(), t),
+            t,
+        }
+    }
+
+    fn tracked(&self, id: ParticleId) -> Option<&Tracked<Particle>> {
+        match self.particles.get(id.index()) {
+            Some(Some((slot_id, tracked))) if *slot_id == id => Some(tracked),
+            _ => None,
+        }
+    }
+
+    fn particle_at(&self, tracked: &Tracked<Particle>, t: f64) -> Particle {
+        let mut particle = tracked.value;
+        if !particle.fixed {
+            particle.mv_accelerated(t - tracked.t, self.gravity);
+        }
+
+        let scene = &self.log.scene;
+        if scene.periodic_x {
+            particle.pos.x = particle.pos.x.rem_euclid(scene.width);
+        }
+        if scene.periodic_y {
+            particle.pos.y = particle.pos.y.rem_euclid(scene.height);
+        }
+        particle
+    }
+}
+
+fn segment_at(tracked: &Tracked<Segment>, t: f64) -> Segment {
+    let segment = &tracked.value;
+    if segment.is_moving() {
+        segment.translated(segment.velocity * (t - tracked.t))
+    } else {
+        *segment
+    }
+}
@@ -0,0 +1,288 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::distribution::Histogram;
+use crate::geom::Vec2;
+use crate::particle::ParticleId;
+
+// Predictions of the Enskog theory for the gas of identical hard
+// disks. Contact value of the pair correlation function g(σ) is
+// given by the Henderson equation of state.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Enskog {
+    // Share of the domain covered by disks
+    pub packing_fraction: f64,
+    pub contact_value: f64,
+    pub mean_free_path: f64,
+    pub mean_free_time: f64,
+    // Collisions per particle per unit of time
+    pub collision_frequency: f64,
+}
+
+impl Enskog {
+    // Gas of `n` disks with the diameter `d` and the mass `m` at the temperature
+    // `t` in the domain of the area `area`. Dilute gas limit is the Boltzmann
+    // theory: the mean free path is 1 / (2√2 * density * d).
+    pub fn new(n: usize, d: f64, m: f64, t: f64, area: f64) -> Enskog {
+        let density = n as f64 / area;
+        let packing_fraction = density * PI * d * d / 4.;
+        let contact_value = (1. - 7. / 16. * packing_fraction) / (1. - packing_fraction).powi(2);
+
+        // Particle sweeps the strip of width 2d, and the mean
+        // relative speed of two particles is √2 times the mean speed
+        let mean_speed = (PI * t / (2. * m)).sqrt();
+        let collision_frequency = 2. * 2f64.sqrt() * mean_speed * density * d * contact_value;
+        Enskog {
+            packing_fraction,
+            contact_value,
+            mean_free_path: mean_speed / collision_frequency,
+            mean_free_time: 1. / collision_frequency,
+            collision_frequency,
+        }
+    }
+}
+
+// Measured free flights between successive collisions of particles.
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FreePaths {
+    count: u64,
+    total_path: f64,
+    total_time: f64,
+    paths: Histogram,
+    times: Histogram,
+}
+
+#[wasm_bindgen]
+impl FreePaths {
+    // Number of completed free flights.
+    pub fn get_count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn get_mean_free_path(&self) -> Option<f64> {
+        self.mean(self.total_path)
+    }
+
+    pub fn get_mean_free_time(&self) -> Option<f64> {
+        self.mean(self.total_time)
+    }
+
+    pub fn get_path_histogram(&self) -> Histogram {
+        self.paths.clone()
+    }
+
+    pub fn get_time_histogram(&self) -> Histogram {
+        self.times.clone()
+    }
+}
+
+impl FreePaths {
+    pub fn new(max_path: f64, max_time: f64, bins: usize) -> FreePaths {
+        FreePaths {
+            count: 0,
+            total_path: 0.,
+            total_time: 0.,
+            paths: Histogram::new(0., max_path, bins),
+            times: Histogram::new(0., max_time, bins),
+        }
+    }
+
+    pub fn paths(&self) -> &Histogram {
+        &self.paths
+    }
+
+    pub fn times(&self) -> &Histogram {
+        &self.times
+    }
+
+    fn add(&mut self, path: f64, time: f64) {
+        self.count += 1;
+        self.total_path += path;
+        self.total_time += time;
+        self.paths.add(path);
+        self.times.add(time);
+    }
+
+    fn mean(&self, total: f64) -> Option<f64> {
+        if self.count > 0 {
+            Some(total / self.count as f64)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Flight {
+    id: ParticleId,
+    // Moment of the last collision with another particle
+    collided: f64,
+    // Distance flown since the last collision up to the moment `since`
+    distance: f64,
+    since: f64,
+}
+
+// Follows particles between their collisions with other moving
+// particles. Bounces off segments and fixed particles change the
+// direction, but don't end the free flight. Flights that began
+// before the tracking started are skipped.
+#[derive(Debug, Clone)]
+pub(crate) struct FreePathTracker {
+    // Indexed by slots, like in the simulation
+    flights: Vec<Option<Flight>>,
+    pub(crate) stats: FreePaths,
+}
+
+impl FreePathTracker {
+    pub(crate) fn new(max_path: f64, max_time: f64, bins: usize) -> FreePathTracker {
+        FreePathTracker {
+            flights: Vec::new(),
+            stats: FreePaths::new(max_path, max_time, bins),
+        }
+    }
+
+    // Particle `id` collides with another one at the moment `t`, having
+    // velocity `v` under the acceleration `a` right before the collision.
+    pub(crate) fn collided(&mut self, id: ParticleId, t: f64, v: Vec2, a: Vec2) {
+        if let Some(flight) = self.flight(id) {
+            let path = flight.distance + path_length(v, a, t - flight.since);
+            let time = t - flight.collided;
+            self.stats.add(path, time);
+        }
+
+        if self.flights.len() <= id.index() {
+            self.flights.resize(id.index() + 1, None);
+        }
+        self.flights[id.index()] = Some(Flight {
+            id,
+            collided: t,
+            distance: 0.,
+            since: t,
+        });
+    }
+
+    // Velocity of the particle changes without the collision with another
+    // particle, e.g. it bounces off the segment or gravity is changed.
+    pub(crate) fn turned(&mut self, id: ParticleId, t: f64, v: Vec2, a: Vec2) {
+        if let Some(flight) = self.flight(id) {
+            let distance = flight.distance + path_length(v, a, t - flight.since);
+            self.flights[id.index()] = Some(Flight {
+                distance,
+                since: t,
+                ..flight
+            });
+        }
+    }
+
+    // The particle is removed or moved by hand, its flight is not free.
+    pub(crate) fn interrupt(&mut self, id: ParticleId) {
+        if self.flight(id).is_some() {
+            self.flights[id.index()] = None;
+        }
+    }
+
+    // Flights in progress are dropped, the collected statistics stay.
+    pub(crate) fn interrupt_all(&mut self) {
+        self.flights.clear();
+    }
+
+    fn flight(&self, id: ParticleId) -> Option<Flight> {
+        match self.flights.get(id.index()) {
+            Some(Some(flight)) if flight.id == id => Some(*flight),
+            _ => None,
+        }
+    }
+}
+
+// Length of the path flown during the time `dt` with the acceleration
+// `a`, that ends with the velocity `v`. The path is a parabola arc.
+fn path_length(v: Vec2, a: Vec2, dt: f64) -> f64 {
+    let k = a.len();
+    if k == 0. {
+        return v.len() * dt;
+    }
+
+    // Speed is k * sqrt(u^2 + c^2), where `u` is the time
+    // shifted to the moment when the speed is the lowest
+    let u1 = v * a / (k * k);
+    let u0 = u1 - dt;
+    let c = (v.cross(&a) / (k * k)).abs();
+    let primitive = |u: f64| {
+        if c == 0. {
+            0.5 * u * u.abs()
+        } else {
+            0.5 * (u * (u * u + c * c).sqrt() + c * c * (u / c).asinh())
+        }
+    };
+    k * (primitive(u1) - primitive(u0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compare_floats;
+
+    #[test]
+    fn test_path_length() {
+        let v = Vec2 { x: 3., y: -4. };
+        compare_floats!(path_length(v, Vec2::default(), 2.), 10.);
+
+        // Vertical throw up and down
+        let g = Vec2 { x: 0., y: -10. };
+        compare_floats!(path_length(Vec2 { x: 0., y: -10. }, g, 2.), 10.);
+
+        // Sum of short chords approximates the arc
+        let (dt, steps) = (1.5, 100000);
+        let v0 = Vec2 { x: 2., y: 5. };
+        let chords: f64 = (0..steps)
+            .map(|i| {
+                let t = dt * (i as f64 + 0.5) / steps as f64;
+                (v0 + g * t).len() * dt / steps as f64
+            })
+            .sum();
+        assert!((path_length(v0 + g * dt, g, dt) - chords).abs() < 1e-8);
+    }
+
+    #[test]
+    fn test_free_path_tracker() {
+        let id = ParticleId::new(1, 0);
+        let a = Vec2::default();
+        let mut tracker = FreePathTracker::new(10., 10., 5);
+
+        // The first flight begins before the tracking
+        tracker.turned(id, 1., Vec2 { x: 1., y: 0. }, a);
+        tracker.collided(id, 2., Vec2 { x: 1., y: 0. }, a);
+        assert_eq!(tracker.stats.get_count(), 0);
+
+        tracker.turned(id, 3., Vec2 { x: 2., y: 0. }, a);
+        tracker.collided(id, 5., Vec2 { x: 0., y: 1. }, a);
+        assert_eq!(tracker.stats.get_count(), 1);
+        assert_eq!(tracker.stats.get_mean_free_path(), Some(4.));
+        assert_eq!(tracker.stats.get_mean_free_time(), Some(3.));
+        assert_eq!(tracker.stats.paths().counts(), &[0, 0, 1, 0, 0]);
+
+        tracker.interrupt(id);
+        tracker.collided(id, 6., Vec2 { x: 0., y: 1. }, a);
+        assert_eq!(tracker.stats.get_count(), 1);
+    }
+
+    #[test]
+    fn test_enskog() {
+        // Dilute gas
+        let enskog = Enskog::new(10, 0.1, 1., 2., 1e4);
+        assert!((enskog.contact_value - 1.).abs() < 1e-4);
+        compare_floats!(
+            enskog.mean_free_path,
+            1. / (2. * 2f64.sqrt() * 1e-3 * 0.1 * enskog.contact_value)
+        );
+        compare_floats!(enskog.mean_free_time * enskog.collision_frequency, 1.);
+
+        let dense = Enskog::new(100, 5., 1., 2., 1e4);
+        compare_floats!(dense.packing_fraction, 0.25 * PI / 4.);
+        assert!(dense.contact_value > 1.);
+    }
+}
@@ -4,11 +4,13 @@ use sha2::Sha256;
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
+use crate::particle::ParticleId;
+
 pub type HmacSha256 = Hmac<Sha256>;
 
 pub struct GameParams {
-    // Player's particle index
-    pub p_particle: usize,
+    // Player's particle handle
+    pub p_particle: ParticleId,
     pub player_uuid: String,
     pub player_name: String,
     pub game_end_cb: js_sys::Function,
@@ -19,7 +21,7 @@ pub struct GameParams {
 
 impl GameParams {
     pub fn new(
-        p_particle: usize,
+        p_particle: ParticleId,
         player_uuid: String,
         player_name: String,
         game_started_tick: f64,
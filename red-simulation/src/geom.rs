@@ -3,7 +3,7 @@ use std::ops::{Add, AddAssign, Mul, Sub, SubAssign};
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
-#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Vec2 {
     pub x: f64,
     pub y: f64,
@@ -84,6 +84,12 @@ impl Vec2 {
         self.x * self.x + self.y * self.y
     }
 
+    // Z-component of the 3D cross product.
+    #[inline]
+    pub fn cross(&self, other: &Vec2) -> f64 {
+        self.x * other.y - self.y * other.x
+    }
+
     #[allow(dead_code)]
     pub fn is_collinear(&self, other: &Vec2) -> bool {
         self.x * other.y - self.y * other.x == 0.
@@ -121,8 +127,9 @@ impl Vec2 {
 // Segment is a section between two points.
 // Must always be imutable object, because `n`, `v`, `line`
 // attributes depend on `p1` and `p2` fields.
+// Moving segment is replaced by its translated copy.
 #[wasm_bindgen]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Segment {
     // First point of the segment
     pub p1: Vec2,
@@ -134,6 +141,14 @@ pub struct Segment {
     pub v: Vec2,
     // Line correspoding to the segment
     pub line: Line,
+    // Coefficient of restitution, the simulation's default is used if not set
+    pub restitution: Option<f64>,
+    // Velocity of the segment, it moves without rotation
+    pub velocity: Vec2,
+    // Thermal wall keeps this temperature: bounced particles leave
+    // it with random velocities, see `pvs::thermal_collision`
+    #[serde(default)]
+    pub temperature: Option<f64>,
 }
 
 #[wasm_bindgen]
@@ -141,6 +156,27 @@ impl Segment {
     pub fn new(ax: f64, ay: f64, bx: f64, by: f64) -> Segment {
         Segment::from_points(Vec2 { x: ax, y: ay }, Vec2 { x: bx, y: by })
     }
+
+    pub fn with_restitution(&self, restitution: f64) -> Segment {
+        Segment {
+            restitution: Some(restitution),
+            ..*self
+        }
+    }
+
+    pub fn with_velocity(&self, vx: f64, vy: f64) -> Segment {
+        Segment {
+            velocity: Vec2 { x: vx, y: vy },
+            ..*self
+        }
+    }
+
+    pub fn with_temperature(&self, temperature: f64) -> Segment {
+        Segment {
+            temperature: Some(temperature),
+            ..*self
+        }
+    }
 }
 
 impl Segment {
@@ -150,7 +186,32 @@ impl Segment {
         let n = v.norm();
         let line = Line::from_two_points(&p1, &p2);
 
-        Segment { p1, p2, n, v, line }
+        Segment {
+            p1,
+            p2,
+            n,
+            v,
+            line,
+            restitution: None,
+            velocity: Vec2 { x: 0., y: 0. },
+            temperature: None,
+        }
+    }
+
+    #[inline]
+    pub fn is_moving(&self) -> bool {
+        !self.velocity.is_zero()
+    }
+
+    // Copy of the segment shifted by `d`.
+    pub fn translated(&self, d: Vec2) -> Segment {
+        let (p1, p2) = (self.p1 + d, self.p2 + d);
+        Segment {
+            p1,
+            p2,
+            line: Line::from_two_points(&p1, &p2),
+            ..*self
+        }
     }
 
     pub fn create_rectangle_domain(origin: Vec2, width: f64, height: f64) -> Vec<Segment> {
@@ -194,7 +255,7 @@ pub struct Circle {
 
 // Represents the general form of a line equation
 #[wasm_bindgen]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Line {
     pub a: f64,
     pub b: f64,
@@ -0,0 +1,425 @@
+use serde::{Deserialize, Serialize};
+
+use crate::geom::{Segment, Vec2};
+use crate::particle::Particle;
+use crate::poly;
+
+// Upper bound for the amount of cells in the grid.
+// Protects us from allocating enormous grids for tiny particles.
+const MAX_CELLS: f64 = 65536.;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Cell {
+    // Indexes of particles, whose centers lie inside the cell
+    pub particles: Vec<usize>,
+    // Indexes of segments, whose bounding boxes overlap the cell
+    pub segments: Vec<usize>,
+}
+
+// Uniform grid, that splits the domain into equal cells.
+// Cell is never smaller than the diameter of the largest particle,
+// so the particle may collide only with particles and segments
+// registered in the same or in the neighbouring cells.
+// Periodic axes are closed into a ring: the last cell
+// on the axis is a neighbour of the first one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Grid {
+    cell_w: f64,
+    cell_h: f64,
+    cols: usize,
+    rows: usize,
+    periodic_x: bool,
+    periodic_y: bool,
+    cells: Vec<Cell>,
+    // Cell index for every particle
+    particle_cells: Vec<usize>,
+}
+
+impl Grid {
+    pub fn new(width: f64, height: f64, min_cell_size: f64) -> Grid {
+        Grid::new_periodic(width, height, min_cell_size, false, false)
+    }
+
+    // Cells tile the domain exactly, so the domain's width
+    // and height are multiples of the cell's width and height.
+    // Periodic axis has at least 3 cells, otherwise the neighbour
+    // from the left and from the right would be the same cell.
+    pub fn new_periodic(
+        width: f64,
+        height: f64,
+        min_cell_size: f64,
+        periodic_x: bool,
+        periodic_y: bool,
+    ) -> Grid {
+        let min_cell_size = min_cell_size
+            .max((width * height / MAX_CELLS).sqrt())
+            .max(crate::utils::EPS);
+        let cols = Self::axis_cells(width, min_cell_size, periodic_x);
+        let rows = Self::axis_cells(height, min_cell_size, periodic_y);
+
+        Grid {
+            cell_w: width / cols as f64,
+            cell_h: height / rows as f64,
+            cols,
+            rows,
+            periodic_x,
+            periodic_y,
+            cells: vec![Cell::default(); cols * rows],
+            particle_cells: Vec::new(),
+        }
+    }
+
+    fn axis_cells(len: f64, min_cell_size: f64, periodic: bool) -> usize {
+        let cells = ((len / min_cell_size).floor() as usize).max(1);
+        if periodic {
+            cells.max(3)
+        } else {
+            cells
+        }
+    }
+
+    // Size of the cell's smallest side.
+    pub fn cell_size(&self) -> f64 {
+        self.cell_w.min(self.cell_h)
+    }
+
+    pub fn cell(&self, index: usize) -> &Cell {
+        &self.cells[index]
+    }
+
+    pub fn particle_cell(&self, p: usize) -> usize {
+        self.particle_cells[p]
+    }
+
+    // Returns index of the cell that contains the point.
+    // Points outside of the domain are attached to the closest border cell.
+    pub fn cell_at(&self, x: f64, y: f64) -> usize {
+        let col = Self::clamp_coord(x / self.cell_w, self.cols);
+        let row = Self::clamp_coord(y / self.cell_h, self.rows);
+        row * self.cols + col
+    }
+
+    #[inline]
+    fn clamp_coord(value: f64, len: usize) -> usize {
+        if value <= 0. {
+            0
+        } else {
+            (value as usize).min(len - 1)
+        }
+    }
+
+    #[inline]
+    fn coords(&self, cell: usize) -> (usize, usize) {
+        (cell % self.cols, cell / self.cols)
+    }
+
+    pub fn insert_particle(&mut self, p: usize, particle: &Particle) {
+        let cell = self.cell_at(particle.pos.x, particle.pos.y);
+        if self.particle_cells.len() <= p {
+            self.particle_cells.resize(p + 1, cell);
+        }
+        self.particle_cells[p] = cell;
+        self.cells[cell].particles.push(p);
+    }
+
+    pub fn move_particle(&mut self, p: usize, to: usize) {
+        self.remove_particle(p);
+        self.cells[to].particles.push(p);
+        self.particle_cells[p] = to;
+    }
+
+    pub fn remove_particle(&mut self, p: usize) {
+        let particles = &mut self.cells[self.particle_cells[p]].particles;
+        if let Some(position) = particles.iter().position(|&i| i == p) {
+            particles.swap_remove(position);
+        }
+    }
+
+    pub fn insert_segment(&mut self, s: usize, segment: &Segment) {
+        let (min_x, max_x) = min_max(segment.p1.x, segment.p2.x);
+        let (min_y, max_y) = min_max(segment.p1.y, segment.p2.y);
+
+        let (col_from, row_from) = self.coords(self.cell_at(min_x, min_y));
+        let (col_to, row_to) = self.coords(self.cell_at(max_x, max_y));
+
+        for row in row_from..=row_to {
+            for col in col_from..=col_to {
+                self.cells[row * self.cols + col].segments.push(s);
+            }
+        }
+    }
+
+    // Iterates over the cell itself and all its neighbours.
+    pub fn neighbours(&self, cell: usize) -> impl Iterator<Item = usize> + '_ {
+        let (col, row) = self.coords(cell);
+        let cols = Self::axis_neighbours(col, self.cols, self.periodic_x);
+        let rows = Self::axis_neighbours(row, self.rows, self.periodic_y);
+
+        rows.into_iter()
+            .flat_map(move |r| cols.clone().into_iter().map(move |c| r * self.cols + c))
+    }
+
+    fn axis_neighbours(coord: usize, len: usize, periodic: bool) -> Vec<usize> {
+        if periodic {
+            let mut coords = vec![(coord + len - 1) % len, coord, (coord + 1) % len];
+            coords.sort_unstable();
+            coords
+        } else {
+            (coord.saturating_sub(1)..=(coord + 1).min(len - 1)).collect()
+        }
+    }
+
+    pub fn are_neighbours(&self, left: usize, right: usize) -> bool {
+        let (lc, lr) = self.coords(left);
+        let (rc, rr) = self.coords(right);
+        Self::axis_distance(lc, rc, self.cols, self.periodic_x) <= 1
+            && Self::axis_distance(lr, rr, self.rows, self.periodic_y) <= 1
+    }
+
+    #[inline]
+    fn axis_distance(a: usize, b: usize, len: usize, periodic: bool) -> usize {
+        let d = a.max(b) - a.min(b);
+        if periodic {
+            d.min(len - d)
+        } else {
+            d
+        }
+    }
+
+    // Translation of the particle, that leaves the cell `from` for the
+    // cell `to` through the periodic border. It's zero for inner borders.
+    pub fn wrap_shift(&self, from: usize, to: usize) -> Vec2 {
+        let (fc, fr) = self.coords(from);
+        let (tc, tr) = self.coords(to);
+        let width = self.cell_w * self.cols as f64;
+        let height = self.cell_h * self.rows as f64;
+
+        Vec2 {
+            x: Self::axis_shift(fc, tc, width),
+            y: Self::axis_shift(fr, tr, height),
+        }
+    }
+
+    #[inline]
+    fn axis_shift(from: usize, to: usize, len: f64) -> f64 {
+        if from > to + 1 {
+            -len
+        } else if to > from + 1 {
+            len
+        } else {
+            0.
+        }
+    }
+
+    // Calculates when the particle moving with acceleration `a` leaves
+    // the `cell` and the index of the cell it enters. Border cells are
+    // never left through the domain border, unless the axis is periodic.
+    pub fn time_to_leave(&self, particle: &Particle, cell: usize, a: Vec2) -> Option<(f64, usize)> {
+        let (col, row) = self.coords(cell);
+        let (pos, v) = (particle.pos, particle.v);
+
+        let x_axis = Axis {
+            len: self.cols,
+            size: self.cell_w,
+            periodic: self.periodic_x,
+        };
+        let y_axis = Axis {
+            len: self.rows,
+            size: self.cell_h,
+            periodic: self.periodic_y,
+        };
+
+        let x_exit = x_axis
+            .exit(pos.x, v.x, a.x, col)
+            .map(|(t, next_col)| (t, row * self.cols + next_col));
+        let y_exit = y_axis
+            .exit(pos.y, v.y, a.y, row)
+            .map(|(t, next_row)| (t, next_row * self.cols + col));
+
+        match (x_exit, y_exit) {
+            (Some(x), Some(y)) => Some(if y.0 < x.0 { y } else { x }),
+            (x, y) => x.or(y),
+        }
+    }
+}
+
+// Cells along one of the grid's axes.
+struct Axis {
+    len: usize,
+    size: f64,
+    periodic: bool,
+}
+
+impl Axis {
+    #[inline]
+    fn next(&self, coord: usize) -> Option<usize> {
+        if coord + 1 < self.len {
+            Some(coord + 1)
+        } else if self.periodic {
+            Some(0)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn prev(&self, coord: usize) -> Option<usize> {
+        if coord > 0 {
+            Some(coord - 1)
+        } else if self.periodic {
+            Some(self.len - 1)
+        } else {
+            None
+        }
+    }
+
+    fn exit(&self, pos: f64, v: f64, a: f64, coord: usize) -> Option<(f64, usize)> {
+        if a != 0. {
+            return self.accelerated_exit(pos, v, a, coord);
+        }
+
+        if v > 0. {
+            let border = (coord + 1) as f64 * self.size;
+            self.next(coord)
+                .map(|next| (((border - pos) / v).max(0.), next))
+        } else if v < 0. {
+            let border = coord as f64 * self.size;
+            self.prev(coord)
+                .map(|prev| (((border - pos) / v).max(0.), prev))
+        } else {
+            None
+        }
+    }
+
+    // Accelerated particle may turn back, so both borders are checked.
+    fn accelerated_exit(&self, pos: f64, v: f64, a: f64, coord: usize) -> Option<(f64, usize)> {
+        let forward = self.next(coord).and_then(|next| {
+            let border = (coord + 1) as f64 * self.size;
+            poly::first_descending_root(&[border - pos, -v, -0.5 * a]).map(|t| (t, next))
+        });
+
+        let backward = self.prev(coord).and_then(|prev| {
+            let border = coord as f64 * self.size;
+            poly::first_descending_root(&[pos - border, v, 0.5 * a]).map(|t| (t, prev))
+        });
+
+        match (forward, backward) {
+            (Some(f), Some(b)) => Some(if b.0 < f.0 { b } else { f }),
+            (f, b) => f.or(b),
+        }
+    }
+}
+
+#[inline]
+fn min_max(a: f64, b: f64) -> (f64, f64) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compare_floats;
+    use crate::geom::Vec2;
+
+    #[test]
+    fn test_grid_dimensions() {
+        let grid = Grid::new(100., 45., 10.);
+
+        // Cells are stretched to tile the domain
+        assert_eq!(grid.cols, 10);
+        assert_eq!(grid.rows, 4);
+        compare_floats!(grid.cell_h, 11.25);
+        assert_eq!(grid.cell_at(15., 25.), 21);
+        // Points outside of the domain are clamped
+        assert_eq!(grid.cell_at(-5., 100.), 30);
+    }
+
+    #[test]
+    fn test_grid_neighbours() {
+        let grid = Grid::new(100., 100., 10.);
+
+        let corner: Vec<usize> = grid.neighbours(0).collect();
+        assert_eq!(corner, vec![0, 1, 10, 11]);
+
+        let inner: Vec<usize> = grid.neighbours(55).collect();
+        assert_eq!(inner, vec![44, 45, 46, 54, 55, 56, 64, 65, 66]);
+
+        assert!(grid.are_neighbours(55, 66));
+        assert!(!grid.are_neighbours(55, 75));
+    }
+
+    #[test]
+    fn test_grid_segments() {
+        let mut grid = Grid::new(100., 100., 10.);
+        grid.insert_segment(0, &Segment::new(5., 5., 25., 15.));
+
+        for cell in &[0, 1, 2, 10, 11, 12] {
+            assert_eq!(grid.cell(*cell).segments, vec![0]);
+        }
+        assert!(grid.cell(3).segments.is_empty());
+    }
+
+    #[test]
+    fn test_grid_time_to_leave() {
+        let mut grid = Grid::new(100., 100., 10.);
+        let particle = Particle::new(15., 12., 5., -1., 1., 1., None);
+        grid.insert_particle(0, &particle);
+
+        let no_acceleration = Vec2 { x: 0., y: 0. };
+        let (t, cell) = grid
+            .time_to_leave(&particle, grid.particle_cell(0), no_acceleration)
+            .unwrap();
+        compare_floats!(t, 1.);
+        assert_eq!(cell, 12);
+
+        grid.move_particle(0, cell);
+        assert!(grid.cell(11).particles.is_empty());
+        assert_eq!(grid.cell(12).particles, vec![0]);
+
+        // Border cells are never left through the domain border
+        let particle = Particle::new(95., 5., 5., 0., 1., 1., None);
+        let cell = grid.cell_at(95., 5.);
+        assert_eq!(grid.time_to_leave(&particle, cell, no_acceleration), None);
+
+        let particle = Particle {
+            v: Vec2 { x: 0., y: 0. },
+            ..particle
+        };
+        assert_eq!(grid.time_to_leave(&particle, 9, no_acceleration), None);
+
+        // Thrown up, but falls back to the cell below
+        let particle = Particle::new(15., 15., 0., -8., 1., 1., None);
+        let gravity = Vec2 { x: 0., y: 10. };
+        let (t, cell) = grid.time_to_leave(&particle, 11, gravity).unwrap();
+        compare_floats!(t, 0.8 + 1.64f64.sqrt());
+        assert_eq!(cell, 21);
+    }
+
+    #[test]
+    fn test_grid_periodic() {
+        let grid = Grid::new_periodic(100., 100., 10., true, false);
+
+        let corner: Vec<usize> = grid.neighbours(0).collect();
+        assert_eq!(corner, vec![0, 1, 9, 10, 11, 19]);
+        assert!(grid.are_neighbours(10, 19));
+        assert!(!grid.are_neighbours(0, 90));
+
+        // Right border leads to the first column
+        let particle = Particle::new(97., 15., 3., 0., 1., 1., None);
+        let no_acceleration = Vec2 { x: 0., y: 0. };
+        let (t, cell) = grid.time_to_leave(&particle, 19, no_acceleration).unwrap();
+        compare_floats!(t, 1.);
+        assert_eq!(cell, 10);
+        assert_eq!(grid.wrap_shift(19, cell), Vec2 { x: -100., y: 0. });
+        assert_eq!(grid.wrap_shift(10, 19), Vec2 { x: 100., y: 0. });
+        assert_eq!(grid.wrap_shift(10, 11), Vec2 { x: 0., y: 0. });
+
+        // Periodic axis always has at least 3 cells
+        let grid = Grid::new_periodic(100., 100., 40., true, true);
+        assert_eq!((grid.cols, grid.rows), (3, 3));
+    }
+}
@@ -1,8 +1,25 @@
+pub mod checkpoint;
 pub mod collisions;
+pub mod diffusion;
+pub mod distribution;
+pub mod event_log;
+pub mod free_path;
 pub mod game;
 pub mod geom;
+pub mod grid;
+pub mod observer;
 pub mod particle;
+pub mod poly;
+pub mod pressure;
+pub mod probes;
+pub mod random;
+pub mod rdf;
+pub mod render;
+pub mod scene;
 pub mod simulation;
+pub mod state_arrays;
+pub mod stats;
+pub mod trajectory;
 pub mod utils;
 
 use wasm_bindgen::prelude::*;
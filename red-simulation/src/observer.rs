@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::geom::Vec2;
+use crate::particle::ParticleId;
+use crate::utils::to_js_value;
+
+// State of the particle's velocity around the collision.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Impact {
+    pub id: ParticleId,
+    pub v_before: Vec2,
+    pub v_after: Vec2,
+}
+
+impl Impact {
+    pub fn dv(&self) -> Vec2 {
+        self.v_after - self.v_before
+    }
+}
+
+// Description of the processed collision, that
+// is passed to every registered observer.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum CollisionReport {
+    ParticleVsParticle {
+        t: f64,
+        p1: Impact,
+        p2: Impact,
+        // Impulse received by the `p1`, `p2` gets the opposite one
+        impulse: Vec2,
+    },
+    ParticleVsSegment {
+        t: f64,
+        p: Impact,
+        // Index of the segment
+        s: usize,
+        // Impulse received by the particle
+        impulse: Vec2,
+    },
+}
+
+impl CollisionReport {
+    pub fn t(&self) -> f64 {
+        match self {
+            Self::ParticleVsParticle { t, .. } => *t,
+            Self::ParticleVsSegment { t, .. } => *t,
+        }
+    }
+}
+
+// Gets notified about every collision processed by the simulation.
+pub trait CollisionObserver {
+    fn on_collision(&mut self, report: &CollisionReport);
+}
+
+impl<F: FnMut(&CollisionReport)> CollisionObserver for F {
+    fn on_collision(&mut self, report: &CollisionReport) {
+        self(report)
+    }
+}
+
+// Observer that forwards reports to the javascript callback.
+pub struct JsCollisionObserver {
+    cb: js_sys::Function,
+}
+
+impl JsCollisionObserver {
+    pub fn new(cb: js_sys::Function) -> JsCollisionObserver {
+        JsCollisionObserver { cb }
+    }
+}
+
+impl CollisionObserver for JsCollisionObserver {
+    fn on_collision(&mut self, report: &CollisionReport) {
+        let this = JsValue::null();
+        let report = to_js_value(report);
+        self.cb.call1(&this, &report).unwrap();
+    }
+}
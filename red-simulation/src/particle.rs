@@ -1,5 +1,6 @@
 use super::geom::{Circle, Vec2};
 use serde::{Deserialize, Serialize};
+use std::ops::{Index, IndexMut};
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
@@ -9,8 +10,16 @@ pub struct Particle {
     pub v: Vec2,
     pub m: f64,
     pub r: f64,
+    #[serde(default)]
     pub collisions_count: u64,
     pub color: Option<RGBA>,
+    // Coefficient of restitution, the simulation's default is used if not set
+    #[serde(default)]
+    pub restitution: Option<f64>,
+    // Fixed particles have infinite mass and never move,
+    // other particles bounce off them like off the wall
+    #[serde(default)]
+    pub fixed: bool,
 }
 
 #[wasm_bindgen]
@@ -31,6 +40,24 @@ impl Particle {
             r,
             collisions_count: 0,
             color,
+            restitution: None,
+            fixed: false,
+        }
+    }
+
+    pub fn with_restitution(&self, restitution: f64) -> Particle {
+        Particle {
+            restitution: Some(restitution),
+            ..*self
+        }
+    }
+
+    // Copy of the particle pinned to its place, velocity is dropped.
+    pub fn as_fixed(&self) -> Particle {
+        Particle {
+            v: Vec2 { x: 0., y: 0. },
+            fixed: true,
+            ..*self
         }
     }
 }
@@ -41,6 +68,23 @@ impl Particle {
         self.pos += self.v * dt;
     }
 
+    // Moves along the parabola with the constant acceleration `a`.
+    #[inline]
+    pub fn mv_accelerated(&mut self, dt: f64, a: Vec2) {
+        self.pos += self.v * dt + a * (0.5 * dt * dt);
+        self.v += a * dt;
+    }
+
+    // Inverse mass, that is zero for the fixed particle.
+    #[inline]
+    pub fn inv_m(&self) -> f64 {
+        if self.fixed {
+            0.
+        } else {
+            1. / self.m
+        }
+    }
+
     pub fn circle(&self) -> Circle {
         Circle {
             p: self.pos,
@@ -49,6 +93,152 @@ impl Particle {
     }
 }
 
+// Stable handle of the particle inside the simulation.
+// Slot `index` may be reused after the particle removal,
+// `generation` tells apart particles that lived in the same slot.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ParticleId {
+    index: usize,
+    generation: u32,
+}
+
+#[wasm_bindgen]
+impl ParticleId {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+impl ParticleId {
+    pub(crate) fn new(index: usize, generation: u32) -> ParticleId {
+        ParticleId { index, generation }
+    }
+}
+
+// Slot storage for particles. Removed particles leave a hole,
+// that is reused by the next inserted particle.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ParticleStore {
+    particles: Vec<Particle>,
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free: Vec<usize>,
+}
+
+impl ParticleStore {
+    pub fn new() -> ParticleStore {
+        Default::default()
+    }
+
+    pub fn insert(&mut self, particle: Particle) -> ParticleId {
+        if let Some(index) = self.free.pop() {
+            self.particles[index] = particle;
+            self.alive[index] = true;
+            self.id(index)
+        } else {
+            self.particles.push(particle);
+            self.generations.push(0);
+            self.alive.push(true);
+            self.id(self.particles.len() - 1)
+        }
+    }
+
+    pub fn remove(&mut self, id: ParticleId) -> Option<Particle> {
+        if !self.contains(id) {
+            return None;
+        }
+        self.alive[id.index] = false;
+        // Invalidates all the outstanding handles for this slot
+        self.generations[id.index] = self.generations[id.index].wrapping_add(1);
+        self.free.push(id.index);
+        Some(self.particles[id.index])
+    }
+
+    pub fn contains(&self, id: ParticleId) -> bool {
+        self.is_alive(id.index) && self.generations[id.index] == id.generation
+    }
+
+    pub fn get(&self, id: ParticleId) -> Option<&Particle> {
+        if self.contains(id) {
+            Some(&self.particles[id.index])
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self, id: ParticleId) -> Option<&mut Particle> {
+        if self.contains(id) {
+            Some(&mut self.particles[id.index])
+        } else {
+            None
+        }
+    }
+
+    // Returns handle of the particle stored in the slot `index`.
+    pub fn id(&self, index: usize) -> ParticleId {
+        ParticleId {
+            index,
+            generation: self.generations[index],
+        }
+    }
+
+    pub fn is_alive(&self, index: usize) -> bool {
+        self.alive.get(index).copied().unwrap_or(false)
+    }
+
+    // Amount of living particles
+    pub fn len(&self) -> usize {
+        self.particles.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Amount of slots, including the empty ones
+    pub fn slots(&self) -> usize {
+        self.particles.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Particle)> {
+        let alive = &self.alive;
+        self.particles
+            .iter()
+            .enumerate()
+            .filter(move |(i, _)| alive[*i])
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut Particle)> {
+        let alive = &self.alive;
+        self.particles
+            .iter_mut()
+            .enumerate()
+            .filter(move |(i, _)| alive[*i])
+    }
+}
+
+// Direct slot access, the caller must be sure that slot is alive.
+impl Index<usize> for ParticleStore {
+    type Output = Particle;
+
+    #[inline]
+    fn index(&self, index: usize) -> &Particle {
+        &self.particles[index]
+    }
+}
+
+impl IndexMut<usize> for ParticleStore {
+    #[inline]
+    fn index_mut(&mut self, index: usize) -> &mut Particle {
+        &mut self.particles[index]
+    }
+}
+
 #[wasm_bindgen]
 #[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub struct RGBA {
@@ -116,6 +306,31 @@ impl RGBA {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_particle_store() {
+        let mut store = ParticleStore::new();
+        let p = Particle::new(1., 2., 3., 4., 1., 1., None);
+
+        let first = store.insert(p);
+        let second = store.insert(p);
+        assert_eq!(store.len(), 2);
+
+        assert_eq!(store.remove(first), Some(p));
+        assert_eq!(store.remove(first), None);
+        assert_eq!(store.get(first), None);
+        assert_eq!(store.get(second), Some(&p));
+        assert_eq!(store.iter().map(|(i, _)| i).collect::<Vec<_>>(), vec![1]);
+
+        // Slot is reused, but the old handle stays invalid
+        let third = store.insert(p);
+        assert_eq!(third.index(), first.index());
+        assert_ne!(third, first);
+        assert!(!store.contains(first));
+        assert!(store.contains(third));
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.slots(), 2);
+    }
+
     #[test]
     fn test_rgba() {
         let rgba = RGBA {
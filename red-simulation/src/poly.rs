@@ -0,0 +1,182 @@
+// Polynomial root finding used by the collision prediction.
+// Coefficients go in ascending order: c[0] + c[1] * t + c[2] * t^2 + ...
+
+// Amount of bisection steps, enough to reach f64 precision.
+const BISECTION_STEPS: usize = 128;
+
+#[inline]
+pub fn eval(c: &[f64], t: f64) -> f64 {
+    c.iter().rev().fold(0., |acc, &k| acc * t + k)
+}
+
+pub fn derivative(c: &[f64]) -> Vec<f64> {
+    c.iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, &k)| k * i as f64)
+        .collect()
+}
+
+// Strips zero leading coefficients, so the last one is never zero.
+fn trim(c: &[f64]) -> &[f64] {
+    let len = c.iter().rposition(|&k| k != 0.).map_or(0, |i| i + 1);
+    &c[..len]
+}
+
+// All the real roots of the polynomial in ascending order.
+// Multiple roots may be reported once or not at all.
+pub fn real_roots(c: &[f64]) -> Vec<f64> {
+    let c = trim(c);
+
+    match c.len() {
+        0 | 1 => Vec::new(),
+        2 => vec![-c[0] / c[1]],
+        3 => quadratic_roots(c[2], c[1], c[0]),
+        _ => {
+            // Roots are separated by the roots of the derivative,
+            // polynomial is monotonic between them.
+            let bound = cauchy_bound(c);
+            let mut points = vec![-bound];
+            points.extend(
+                real_roots(&derivative(c))
+                    .into_iter()
+                    .filter(|x| x.abs() < bound),
+            );
+            points.push(bound);
+
+            let mut roots: Vec<f64> = points
+                .windows(2)
+                .filter_map(|w| bisect(c, w[0], w[1]))
+                .collect();
+            roots.dedup();
+            roots
+        }
+    }
+}
+
+// Numerically stable roots of `a * t^2 + b * t + c`.
+pub fn quadratic_roots(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a == 0. {
+        return if b == 0. { Vec::new() } else { vec![-c / b] };
+    }
+
+    let d = b * b - 4. * a * c;
+    if d < 0. {
+        return Vec::new();
+    }
+
+    let q = -0.5 * (b + b.signum() * d.sqrt());
+    if q == 0. {
+        return vec![0.];
+    }
+
+    let (r1, r2) = (q / a, c / q);
+    if r1 < r2 {
+        vec![r1, r2]
+    } else {
+        vec![r2, r1]
+    }
+}
+
+// Smallest `t >= 0` at which the polynomial turns from positive
+// to negative. If it's already negative (or zero) and decreasing
+// at `t = 0`, then zero is returned.
+pub fn first_descending_root(c: &[f64]) -> Option<f64> {
+    let dc = derivative(c);
+
+    if eval(c, 0.) <= 0. && eval(&dc, 0.) < 0. {
+        return Some(0.);
+    }
+
+    real_roots(c)
+        .into_iter()
+        .find(|&t| t > 0. && eval(&dc, t) < 0.)
+}
+
+// Every real root lies inside `(-bound, bound)`.
+fn cauchy_bound(c: &[f64]) -> f64 {
+    let lead = c[c.len() - 1];
+    1. + c[..c.len() - 1]
+        .iter()
+        .fold(0., |acc: f64, &k| acc.max((k / lead).abs()))
+}
+
+// Finds the root of the monotonic polynomial on `[a, b]`.
+fn bisect(c: &[f64], mut a: f64, mut b: f64) -> Option<f64> {
+    let mut fa = eval(c, a);
+    let fb = eval(c, b);
+
+    if fa == 0. {
+        return Some(a);
+    }
+    if fb == 0. {
+        return Some(b);
+    }
+    if fa.signum() == fb.signum() {
+        return None;
+    }
+
+    for _ in 0..BISECTION_STEPS {
+        let mid = 0.5 * (a + b);
+        if mid <= a || mid >= b {
+            break;
+        }
+
+        let fm = eval(c, mid);
+        if fm == 0. {
+            return Some(mid);
+        }
+        if fm.signum() == fa.signum() {
+            a = mid;
+            fa = fm;
+        } else {
+            b = mid;
+        }
+    }
+    Some(0.5 * (a + b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compare_floats;
+
+    #[test]
+    fn test_quadratic_roots() {
+        // (t - 1) * (t - 3)
+        let roots = real_roots(&[3., -4., 1.]);
+        assert_eq!(roots.len(), 2);
+        compare_floats!(roots[0], 1.);
+        compare_floats!(roots[1], 3.);
+
+        assert!(real_roots(&[1., 0., 1.]).is_empty());
+        assert_eq!(real_roots(&[2., 4., 0.]), vec![-0.5]);
+    }
+
+    #[test]
+    fn test_quartic_roots() {
+        // (t + 2) * (t - 0.5) * (t - 1) * (t - 4)
+        let roots = real_roots(&[-4., 11., -4.5, -3.5, 1.]);
+        let expected = [-2., 0.5, 1., 4.];
+
+        assert_eq!(roots.len(), expected.len());
+        for (root, expected) in roots.iter().zip(expected.iter()) {
+            compare_floats!(root, expected);
+        }
+
+        // t^4 + 1 has no real roots
+        assert!(real_roots(&[1., 0., 0., 0., 1.]).is_empty());
+    }
+
+    #[test]
+    fn test_first_descending_root() {
+        // (t - 1) * (t - 3) is descending at 1
+        compare_floats!(first_descending_root(&[3., -4., 1.]).unwrap(), 1.);
+        // -(t - 1) * (t - 3) is descending at 3
+        compare_floats!(first_descending_root(&[-3., 4., -1.]).unwrap(), 3.);
+        // Already negative and descending
+        assert_eq!(first_descending_root(&[-1., -1.]), Some(0.));
+        // Never negative
+        assert_eq!(first_descending_root(&[1., 0., 1.]), None);
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::geom::Vec2;
+
+// Totals of the momentum received by the segment from particles
+// and of the energy given to them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SegmentLoad {
+    pub hits: u64,
+    pub impulse: Vec2,
+    // Sum of the normal components' magnitudes, the pressure is made of it
+    pub normal_impulse: f64,
+    // Kinetic energy passed to particles in the frame of the segment, it's
+    // the heat of the thermal wall. Moving segment's work isn't included.
+    pub heat: f64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Hit {
+    t: f64,
+    s: usize,
+    normal_impulse: f64,
+}
+
+// Collects hits of particles on segments. Pressure is averaged
+// over the sliding window of the last `window` time units.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct WallMonitor {
+    window: f64,
+    // Moment of the last reset, the window can't start earlier
+    start: f64,
+    loads: Vec<SegmentLoad>,
+    recent: VecDeque<Hit>,
+}
+
+impl WallMonitor {
+    pub(crate) fn new(window: f64) -> WallMonitor {
+        WallMonitor {
+            window,
+            start: 0.,
+            loads: Vec::new(),
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn window(&self) -> f64 {
+        self.window
+    }
+
+    // Hits that have already left the shorter window are lost.
+    pub(crate) fn set_window(&mut self, window: f64) {
+        self.window = window;
+    }
+
+    pub(crate) fn reset(&mut self, t: f64) {
+        self.start = t;
+        self.loads.clear();
+        self.recent.clear();
+    }
+
+    // Registers impulse `impulse` received by the segment `s` with the normal
+    // `n`, while the particle has got the energy `heat` from it.
+    pub(crate) fn hit(&mut self, t: f64, s: usize, impulse: Vec2, n: Vec2, heat: f64) {
+        if self.loads.len() <= s {
+            self.loads.resize(s + 1, Default::default());
+        }
+        let normal_impulse = (impulse * n).abs();
+        let load = &mut self.loads[s];
+        load.hits += 1;
+        load.impulse += impulse;
+        load.normal_impulse += normal_impulse;
+        load.heat += heat;
+
+        self.recent.push_back(Hit {
+            t,
+            s,
+            normal_impulse,
+        });
+        self.forget(t);
+    }
+
+    pub(crate) fn load(&self, s: usize) -> SegmentLoad {
+        self.loads.get(s).copied().unwrap_or_default()
+    }
+
+    // Mean force per unit length on the segments over the window
+    // ending at `t`. `lengths` are the lengths of `segments`.
+    pub(crate) fn pressure(&self, t: f64, segments: &[usize], lengths: f64) -> f64 {
+        let from = (t - self.window).max(self.start);
+        let duration = t - from;
+        if duration <= 0. || lengths <= 0. {
+            return 0.;
+        }
+
+        let impulse: f64 = self
+            .recent
+            .iter()
+            .filter(|hit| hit.t > from && segments.contains(&hit.s))
+            .map(|hit| hit.normal_impulse)
+            .sum();
+        impulse / (duration * lengths)
+    }
+
+    // Mean power given by the segment `s` to particles since the last reset.
+    pub(crate) fn heat_flow(&self, t: f64, s: usize) -> f64 {
+        if t > self.start {
+            self.load(s).heat / (t - self.start)
+        } else {
+            0.
+        }
+    }
+
+    fn forget(&mut self, t: f64) {
+        while let Some(hit) = self.recent.front() {
+            if hit.t > t - self.window {
+                break;
+            }
+            self.recent.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compare_floats;
+
+    #[test]
+    fn test_wall_monitor() {
+        let n = Vec2 { x: 0., y: 1. };
+        let mut monitor = WallMonitor::new(2.);
+        monitor.hit(0.5, 1, Vec2 { x: 1., y: 4. }, n, 0.);
+        monitor.hit(1.5, 1, Vec2 { x: 0., y: -2. }, n, 3.);
+        monitor.hit(1.5, 3, Vec2 { x: 0., y: 1. }, n, -1.);
+
+        let load = monitor.load(1);
+        assert_eq!(load.hits, 2);
+        assert_eq!(load.impulse, Vec2 { x: 1., y: 2. });
+        compare_floats!(load.normal_impulse, 6.);
+        compare_floats!(load.heat, 3.);
+        compare_floats!(monitor.heat_flow(2., 3), -0.5);
+        assert_eq!(monitor.load(0), SegmentLoad::default());
+
+        // Window isn't full yet
+        compare_floats!(monitor.pressure(1.5, &[1], 2.), 6. / 1.5 / 2.);
+        compare_floats!(monitor.pressure(1.5, &[1, 3], 4.), 7. / 1.5 / 4.);
+        // The first hit has left the window
+        compare_floats!(monitor.pressure(3., &[1], 2.), 2. / 2. / 2.);
+
+        monitor.reset(3.);
+        assert_eq!(monitor.load(1).hits, 0);
+        assert_eq!(monitor.pressure(4., &[1], 2.), 0.);
+    }
+}
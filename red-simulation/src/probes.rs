@@ -0,0 +1,294 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::geom::{Segment, Vec2};
+use crate::particle::Particle;
+use crate::poly;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum Shape {
+    Rect { min: Vec2, max: Vec2 },
+    Circle { center: Vec2, r: f64 },
+    Polygon(Vec<Vec2>),
+}
+
+// Part of the domain, particles are inside if their centers are.
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Region {
+    shape: Shape,
+}
+
+#[wasm_bindgen]
+impl Region {
+    pub fn rect(x: f64, y: f64, width: f64, height: f64) -> Region {
+        let (a, b) = (
+            Vec2 { x, y },
+            Vec2 {
+                x: x + width,
+                y: y + height,
+            },
+        );
+        Region {
+            shape: Shape::Rect {
+                min: Vec2 {
+                    x: a.x.min(b.x),
+                    y: a.y.min(b.y),
+                },
+                max: Vec2 {
+                    x: a.x.max(b.x),
+                    y: a.y.max(b.y),
+                },
+            },
+        }
+    }
+
+    pub fn circle(x: f64, y: f64, r: f64) -> Region {
+        Region {
+            shape: Shape::Circle {
+                center: Vec2 { x, y },
+                r,
+            },
+        }
+    }
+
+    // Vertices go as flat coordinates: [x1, y1, x2, y2, ...].
+    pub fn polygon(coords: Vec<f64>) -> Region {
+        Region::from_points(
+            coords
+                .chunks_exact(2)
+                .map(|c| Vec2 { x: c[0], y: c[1] })
+                .collect(),
+        )
+    }
+
+    pub fn area(&self) -> f64 {
+        match &self.shape {
+            Shape::Rect { min, max } => (max.x - min.x) * (max.y - min.y),
+            Shape::Circle { r, .. } => PI * r * r,
+            Shape::Polygon(points) => {
+                let twice: f64 = points
+                    .iter()
+                    .zip(points.iter().cycle().skip(1))
+                    .map(|(a, b)| a.cross(b))
+                    .sum();
+                twice.abs() / 2.
+            }
+        }
+    }
+}
+
+impl Region {
+    // Polygon may be concave, but must not intersect itself.
+    pub fn from_points(points: Vec<Vec2>) -> Region {
+        Region {
+            shape: Shape::Polygon(points),
+        }
+    }
+
+    pub fn contains(&self, p: Vec2) -> bool {
+        match &self.shape {
+            Shape::Rect { min, max } => {
+                min.x <= p.x && p.x <= max.x && min.y <= p.y && p.y <= max.y
+            }
+            Shape::Circle { center, r } => (p - *center).len_sqr() <= r * r,
+            // Even-odd rule: the ray to the right crosses the border odd times
+            Shape::Polygon(points) => {
+                points
+                    .iter()
+                    .zip(points.iter().cycle().skip(1))
+                    .filter(|(a, b)| (a.y > p.y) != (b.y > p.y))
+                    .filter(|(a, b)| p.x < a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x))
+                    .count()
+                    % 2
+                    == 1
+            }
+        }
+    }
+}
+
+// Moving particles inside the region at the moment.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct RegionStats {
+    pub particles: usize,
+    // Particles per unit of area
+    pub density: f64,
+    pub mean_kinetic_energy: f64,
+}
+
+impl RegionStats {
+    pub fn new<'a, I: Iterator<Item = &'a Particle>>(region: &Region, particles: I) -> RegionStats {
+        let (count, energy) = particles
+            .filter(|p| !p.fixed && region.contains(p.pos))
+            .fold((0, 0.), |(count, energy), p| {
+                (count + 1, energy + 0.5 * p.m * p.v.len_sqr())
+            });
+
+        let area = region.area();
+        RegionStats {
+            particles: count,
+            density: if area > 0. { count as f64 / area } else { 0. },
+            mean_kinetic_energy: if count > 0 { energy / count as f64 } else { 0. },
+        }
+    }
+}
+
+// Segment that counts particles' centers crossing it, particles
+// fly through it freely. Forward is the direction of the segment's
+// normal: left to right for vertical gates, downwards for horizontal.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Gate {
+    pub(crate) segment: Segment,
+    pub forward: u64,
+    pub backward: u64,
+    // Moment since the crossings are counted
+    pub since: f64,
+}
+
+impl Gate {
+    pub fn new(segment: Segment, since: f64) -> Gate {
+        Gate {
+            segment: Segment {
+                velocity: Vec2::default(),
+                ..segment
+            },
+            forward: 0,
+            backward: 0,
+            since,
+        }
+    }
+
+    pub fn segment(&self) -> &Segment {
+        &self.segment
+    }
+
+    // Net number of particles crossed forward per unit of time.
+    pub fn flux(&self, t: f64) -> f64 {
+        if t > self.since {
+            (self.forward as f64 - self.backward as f64) / (t - self.since)
+        } else {
+            0.
+        }
+    }
+
+    pub(crate) fn reset(&mut self, t: f64) {
+        self.forward = 0;
+        self.backward = 0;
+        self.since = t;
+    }
+
+    // Counts the crossing of the particle, if its center is on the gate.
+    // Stale predictions, e.g. made before the particle has been wrapped
+    // over the periodic border, don't pass this check.
+    pub(crate) fn cross(&mut self, p: &Particle) -> bool {
+        let s = (p.pos - self.segment.p1) * self.segment.n;
+        let on_line = p.pos - self.segment.n * s;
+        if s.abs() > CROSSING_TOLERANCE || !self.segment.contains_point(&on_line) {
+            return false;
+        }
+
+        if p.v * self.segment.n > 0. {
+            self.forward += 1;
+        } else {
+            self.backward += 1;
+        }
+        true
+    }
+
+    // Time until the particle's center crosses the gate under the
+    // acceleration `a`. The particle, that has just crossed it, is
+    // considered to be exactly on the line, so the same crossing
+    // isn't found again.
+    pub(crate) fn time_to_cross(&self, p: &Particle, a: Vec2, just_crossed: bool) -> Option<f64> {
+        let segment = &self.segment;
+        let s0 = if just_crossed {
+            0.
+        } else {
+            (p.pos - segment.p1) * segment.n
+        };
+        let (vn, an) = (p.v * segment.n, 0.5 * (a * segment.n));
+
+        let on_gate = |t: f64| {
+            let pos = p.pos + p.v * t + a * (0.5 * t * t);
+            segment.contains_point(&(pos - segment.n * ((pos - segment.p1) * segment.n)))
+        };
+
+        poly::real_roots(&[s0, vn, an])
+            .into_iter()
+            .filter(|&t| t > 0. && t.is_finite())
+            .find(|&t| on_gate(t))
+    }
+}
+
+// Distance from the gate's line, at which the crossing
+// predicted for this moment is still counted.
+const CROSSING_TOLERANCE: f64 = 1e-6;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compare_floats;
+
+    #[test]
+    fn test_regions() {
+        let rect = Region::rect(10., 10., -4., 2.);
+        assert!(rect.contains(Vec2 { x: 7., y: 11. }));
+        assert!(!rect.contains(Vec2 { x: 11., y: 11. }));
+        compare_floats!(rect.area(), 8.);
+
+        let circle = Region::circle(0., 0., 2.);
+        assert!(circle.contains(Vec2 { x: 1., y: -1. }));
+        assert!(!circle.contains(Vec2 { x: 2., y: 1. }));
+        compare_floats!(circle.area(), 4. * PI);
+
+        // Concave "L" shape
+        let polygon = Region::polygon(vec![0., 0., 4., 0., 4., 1., 1., 1., 1., 4., 0., 4.]);
+        assert!(polygon.contains(Vec2 { x: 0.5, y: 3. }));
+        assert!(polygon.contains(Vec2 { x: 3., y: 0.5 }));
+        assert!(!polygon.contains(Vec2 { x: 3., y: 3. }));
+        compare_floats!(polygon.area(), 7.);
+
+        let particles = [
+            Particle::new(1., 1., 2., 0., 2., 1., None),
+            Particle::new(2., 2., 0., 0., 1., 1., None),
+            Particle::new(0., 0., 0., 0., 1., 1., None).as_fixed(),
+            Particle::new(5., 5., 1., 1., 1., 1., None),
+        ];
+        let stats = RegionStats::new(&Region::rect(0., 0., 4., 4.), particles.iter());
+        assert_eq!(stats.particles, 2);
+        compare_floats!(stats.density, 2. / 16.);
+        compare_floats!(stats.mean_kinetic_energy, 2.);
+    }
+
+    #[test]
+    fn test_gate() {
+        let mut gate = Gate::new(Segment::new(5., 0., 5., 10.), 0.);
+        assert_eq!(gate.segment().n, Vec2 { x: 1., y: 0. });
+
+        let p = Particle::new(1., 5., 2., 0., 1., 1., None);
+        compare_floats!(gate.time_to_cross(&p, Vec2::default(), false).unwrap(), 2.);
+        // Passes by the gate
+        let p = Particle::new(1., 12., 2., 0., 1., 1., None);
+        assert_eq!(gate.time_to_cross(&p, Vec2::default(), false), None);
+
+        // Thrown through the gate and falling back
+        let g = Vec2 { x: -1., y: 0. };
+        let p = Particle::new(5., 5., 2., 0., 1., 1., None);
+        assert!(gate.cross(&p));
+        compare_floats!(gate.time_to_cross(&p, g, true).unwrap(), 4.);
+        let mut p = p;
+        p.mv_accelerated(4., g);
+        assert!(gate.cross(&p));
+        assert_eq!((gate.forward, gate.backward), (1, 1));
+
+        // The particle is far from the gate
+        assert!(!gate.cross(&Particle::new(1., 5., 2., 0., 1., 1., None)));
+        compare_floats!(gate.flux(2.), 0.);
+        gate.reset(2.);
+        assert_eq!(gate.forward, 0);
+    }
+}
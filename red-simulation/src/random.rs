@@ -0,0 +1,68 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+// Small pseudorandom generator (SplitMix64). The simulation owns it,
+// so the same seed gives the same run and checkpoints continue the
+// same sequence of numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform number in (0, 1), both ends are excluded,
+    // so its logarithm is always finite and non-zero.
+    pub fn uniform(&mut self) -> f64 {
+        ((self.next_u64() >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+    }
+
+    // Standard normal number by the Box-Muller transform.
+    pub fn normal(&mut self) -> f64 {
+        let r = (-2. * self.uniform().ln()).sqrt();
+        r * (2. * PI * self.uniform()).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random() {
+        let mut a = Random::new(42);
+        let mut b = Random::new(42);
+        assert_eq!(a.next_u64(), b.next_u64());
+        assert_ne!(a.next_u64(), Random::new(43).next_u64());
+
+        let n = 100000;
+        let (mut sum, mut sum_sqr) = (0., 0.);
+        for _ in 0..n {
+            let u = a.uniform();
+            assert!(0. < u && u < 1.);
+            sum += u;
+        }
+        assert!((sum / n as f64 - 0.5).abs() < 0.01);
+
+        sum = 0.;
+        for _ in 0..n {
+            let x = a.normal();
+            sum += x;
+            sum_sqr += x * x;
+        }
+        assert!((sum / n as f64).abs() < 0.02);
+        assert!((sum_sqr / n as f64 - 1.).abs() < 0.02);
+    }
+}
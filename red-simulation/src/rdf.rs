@@ -0,0 +1,250 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::geom::Vec2;
+
+// Radial distribution function g(r) accumulated over many moments.
+// It's the number of particle pairs at the distance r compared with
+// the one of the ideal gas in the same domain. Near the walls there are
+// fewer pairs at every distance, so the ideal gas count takes the shape
+// of the domain into account, see `ideal_pairs`. Along periodic axes
+// the distance to the closest image of the particle is used.
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RadialDistribution {
+    width: f64,
+    height: f64,
+    periodic_x: bool,
+    periodic_y: bool,
+    r_max: f64,
+    counts: Vec<u64>,
+    samples: u64,
+    // Sum of the numbers of pairs over the samples
+    pairs: f64,
+}
+
+#[wasm_bindgen]
+impl RadialDistribution {
+    pub fn get_r_max(&self) -> f64 {
+        self.r_max
+    }
+
+    pub fn get_bin_width(&self) -> f64 {
+        self.r_max / self.counts.len() as f64
+    }
+
+    // Number of the moments accumulated.
+    pub fn get_samples(&self) -> u64 {
+        self.samples
+    }
+
+    pub fn get_counts(&self) -> Vec<u64> {
+        self.counts.clone()
+    }
+
+    pub fn get_centers(&self) -> Vec<f64> {
+        let width = self.get_bin_width();
+        (0..self.counts.len())
+            .map(|i| width * (i as f64 + 0.5))
+            .collect()
+    }
+
+    pub fn get_values(&self) -> Vec<f64> {
+        let width = self.get_bin_width();
+        let area = self.width * self.height;
+        self.counts
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| {
+                let ideal =
+                    self.ideal_pairs(width * (i + 1) as f64) - self.ideal_pairs(width * i as f64);
+                let expected = self.pairs * ideal / (area * area);
+                if expected > 0. {
+                    count as f64 / expected
+                } else {
+                    0.
+                }
+            })
+            .collect()
+    }
+}
+
+impl RadialDistribution {
+    // Distances longer than the half of the periodic domain or than
+    // the domain with walls are ambiguous, so `r_max` is limited.
+    pub fn new(
+        width: f64,
+        height: f64,
+        periodic_x: bool,
+        periodic_y: bool,
+        r_max: f64,
+        bins: usize,
+    ) -> RadialDistribution {
+        let limit = |len: f64, periodic: bool| if periodic { len / 2. } else { len };
+        RadialDistribution {
+            width,
+            height,
+            periodic_x,
+            periodic_y,
+            r_max: r_max
+                .min(limit(width, periodic_x))
+                .min(limit(height, periodic_y)),
+            counts: vec![0; bins.max(1)],
+            samples: 0,
+            pairs: 0.,
+        }
+    }
+
+    // Adds pair distances of the particles at the same moment.
+    pub fn add_sample(&mut self, positions: &[Vec2]) {
+        let width = self.get_bin_width();
+        for (i, a) in positions.iter().enumerate() {
+            for b in &positions[i + 1..] {
+                let r = self.distance(*a, *b);
+                if r < self.r_max {
+                    let bin = ((r / width) as usize).min(self.counts.len() - 1);
+                    self.counts[bin] += 1;
+                }
+            }
+        }
+        let n = positions.len() as f64;
+        self.pairs += n * (n - 1.) / 2.;
+        self.samples += 1;
+    }
+
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    fn distance(&self, a: Vec2, b: Vec2) -> f64 {
+        let closest = |d: f64, len: f64, periodic: bool| {
+            if periodic {
+                d - len * (d / len).round()
+            } else {
+                d
+            }
+        };
+        let d = a - b;
+        Vec2 {
+            x: closest(d.x, self.width, self.periodic_x),
+            y: closest(d.y, self.height, self.periodic_y),
+        }
+        .len()
+    }
+
+    // Integral of 2πr * γ(r) from 0 to `r`. Here γ(r) is the area of the
+    // domain overlapping with its copy shifted by r, averaged over the
+    // directions. For the pair of the uniformly distributed points the
+    // density of the distance r is 2πr * γ(r) / A². Walls along x reduce
+    // the overlap by r|cos θ| * height, walls along y by r|sin θ| * width.
+    fn ideal_pairs(&self, r: f64) -> f64 {
+        let walls_x = if self.periodic_x { 0. } else { 1. };
+        let walls_y = if self.periodic_y { 0. } else { 1. };
+        PI * self.width * self.height * r * r
+            - 4. / 3. * (walls_x * self.height + walls_y * self.width) * r.powi(3)
+            + walls_x * walls_y * r.powi(4) / 2.
+    }
+}
+
+// Samples g(r) every `interval` of the simulation time.
+#[derive(Debug, Clone)]
+pub(crate) struct RdfSampler {
+    start: f64,
+    interval: f64,
+    samples: u64,
+    pub(crate) active: bool,
+    pub(crate) rdf: RadialDistribution,
+}
+
+impl RdfSampler {
+    pub(crate) fn new(start: f64, interval: f64, rdf: RadialDistribution) -> RdfSampler {
+        RdfSampler {
+            start,
+            interval,
+            samples: 0,
+            active: true,
+            rdf,
+        }
+    }
+
+    // Moment of the next sample, if it's due not later than `t`.
+    pub(crate) fn next_sample(&self, t: f64) -> Option<f64> {
+        let next = self.next_t();
+        if self.active && next <= t {
+            Some(next)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn sample(&mut self, positions: &[Vec2]) {
+        self.rdf.add_sample(positions);
+        self.samples += 1;
+    }
+
+    // Skips samples in the past, e.g. after the checkpoint is restored.
+    pub(crate) fn skip_to(&mut self, t: f64) {
+        while self.next_t() < t {
+            self.samples += 1;
+        }
+    }
+
+    fn next_t(&self) -> f64 {
+        self.start + self.samples as f64 * self.interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    // Ideal gas has g(r) = 1 at every distance.
+    fn check_uniform(periodic_x: bool, periodic_y: bool) {
+        let (width, height) = (30., 20.);
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut rdf = RadialDistribution::new(width, height, periodic_x, periodic_y, 100., 10);
+        for _ in 0..100 {
+            let positions: Vec<Vec2> = (0..200)
+                .map(|_| Vec2 {
+                    x: rng.gen_range(0. ..width),
+                    y: rng.gen_range(0. ..height),
+                })
+                .collect();
+            rdf.add_sample(&positions);
+        }
+
+        assert_eq!(rdf.get_samples(), 100);
+        for g in rdf.get_values() {
+            assert!((g - 1.).abs() < 0.05, "{:?}", rdf.get_values());
+        }
+    }
+
+    #[test]
+    fn test_rdf_normalization() {
+        check_uniform(false, false);
+        check_uniform(true, false);
+        check_uniform(false, true);
+        check_uniform(true, true);
+    }
+
+    #[test]
+    fn test_rdf() {
+        let mut rdf = RadialDistribution::new(10., 10., true, false, 20., 4);
+        // Limited by the half of the periodic domain
+        assert_eq!(rdf.get_r_max(), 5.);
+        assert_eq!(rdf.get_centers(), vec![0.625, 1.875, 3.125, 4.375]);
+
+        rdf.add_sample(&[
+            Vec2 { x: 1., y: 5. },
+            // Closer through the periodic border
+            Vec2 { x: 9., y: 5. },
+            Vec2 { x: 1., y: 8. },
+            Vec2 { x: 1., y: 1. },
+        ]);
+        assert_eq!(rdf.counts(), &[0, 1, 2, 2]);
+        assert_eq!(rdf.get_samples(), 1);
+    }
+}
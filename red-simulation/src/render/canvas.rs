@@ -0,0 +1,51 @@
+use web_sys::CanvasRenderingContext2d;
+
+use super::Renderer;
+use crate::geom::Vec2;
+use crate::particle::RGBA;
+
+// Browser backend, draws on the 2D context of the canvas.
+pub struct CanvasRenderer<'a> {
+    ctx: &'a CanvasRenderingContext2d,
+}
+
+impl<'a> CanvasRenderer<'a> {
+    pub fn new(ctx: &'a CanvasRenderingContext2d) -> CanvasRenderer<'a> {
+        CanvasRenderer { ctx }
+    }
+}
+
+impl<'a> Renderer for CanvasRenderer<'a> {
+    fn clear(&mut self, width: f64, height: f64) {
+        self.ctx.clear_rect(0.0, 0.0, width, height);
+    }
+
+    fn set_fill_color(&mut self, color: RGBA) {
+        self.ctx.set_fill_style_str(&color.as_css_hex());
+    }
+
+    fn set_stroke_color(&mut self, color: RGBA) {
+        self.ctx.set_stroke_style_str(&color.as_css_hex());
+    }
+
+    fn circle(&mut self, center: Vec2, r: f64, fill: bool) {
+        let ctx = self.ctx;
+        ctx.begin_path();
+        ctx.arc(center.x, center.y, r, 0.0, 2.0 * std::f64::consts::PI)
+            .unwrap();
+        ctx.close_path();
+        if fill {
+            ctx.fill();
+        }
+        ctx.stroke();
+    }
+
+    fn line(&mut self, p1: Vec2, p2: Vec2) {
+        let ctx = self.ctx;
+        ctx.begin_path();
+        ctx.move_to(p1.x, p1.y);
+        ctx.line_to(p2.x, p2.y);
+        ctx.close_path();
+        ctx.stroke();
+    }
+}
@@ -0,0 +1,128 @@
+mod canvas;
+mod raster;
+mod svg;
+
+pub use canvas::CanvasRenderer;
+pub use raster::{render_frames, write_apng, write_gif, RasterError, RasterImage, RasterRenderer};
+pub use svg::{time_lapse_svg, SvgRenderer};
+
+use crate::geom::Vec2;
+use crate::particle::RGBA;
+use crate::simulation::Simulation;
+
+// Drawing backend. Backends start with black fill and stroke colors.
+pub trait Renderer {
+    // Clears the picture of the given size.
+    fn clear(&mut self, width: f64, height: f64);
+    fn set_fill_color(&mut self, color: RGBA);
+    fn set_stroke_color(&mut self, color: RGBA);
+    // Strokes the circle, `fill` fills it with the fill color first.
+    fn circle(&mut self, center: Vec2, r: f64, fill: bool);
+    fn line(&mut self, p1: Vec2, p2: Vec2);
+}
+
+// Draws the current state of the simulation. Particles crossing
+// periodic borders are drawn on both sides of the domain.
+pub fn draw<R: Renderer + ?Sized>(sim: &Simulation, renderer: &mut R) {
+    renderer.clear(sim.width(), sim.height());
+    draw_particles(sim, renderer);
+    draw_segments(sim, renderer);
+}
+
+pub fn draw_particles<R: Renderer + ?Sized>(sim: &Simulation, renderer: &mut R) {
+    for (_, particle) in sim.particles() {
+        if let Some(color) = particle.color {
+            renderer.set_fill_color(color);
+        }
+        for pos in sim.draw_positions(particle) {
+            renderer.circle(pos, particle.r, particle.color.is_some());
+        }
+    }
+}
+
+pub fn draw_segments<R: Renderer + ?Sized>(sim: &Simulation, renderer: &mut R) {
+    for segment in sim.drawn_segments() {
+        renderer.line(segment.p1, segment.p2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geom::Segment;
+    use crate::particle::Particle;
+    use crate::simulation::DrawParams;
+
+    // Backend that remembers what it was asked to draw.
+    #[derive(Default)]
+    struct Recorder {
+        commands: Vec<String>,
+    }
+
+    impl Renderer for Recorder {
+        fn clear(&mut self, width: f64, height: f64) {
+            self.commands.push(format!("clear {} {}", width, height));
+        }
+
+        fn set_fill_color(&mut self, color: RGBA) {
+            self.commands.push(format!("fill {}", color.as_css_hex()));
+        }
+
+        fn set_stroke_color(&mut self, color: RGBA) {
+            self.commands.push(format!("stroke {}", color.as_css_hex()));
+        }
+
+        fn circle(&mut self, center: Vec2, r: f64, fill: bool) {
+            self.commands
+                .push(format!("circle {} {} {} {}", center.x, center.y, r, fill));
+        }
+
+        fn line(&mut self, p1: Vec2, p2: Vec2) {
+            self.commands
+                .push(format!("line {} {} {} {}", p1.x, p1.y, p2.x, p2.y));
+        }
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut sim = Simulation::new(100., 50., 60, Some(DrawParams::new(false)));
+        sim.set_periodic(true, false);
+        sim.add_segment(&Segment::new(10., 40., 90., 40.));
+        sim.add_particle(&Particle::new(
+            30.,
+            20.,
+            0.,
+            0.,
+            1.,
+            5.,
+            RGBA::from_css_hex("#FF0000"),
+        ));
+        sim.add_particle(&Particle::new(2., 10., 0., 0., 1., 3., None));
+
+        let mut recorder = Recorder::default();
+        sim.render(&mut recorder);
+        assert_eq!(
+            recorder.commands,
+            vec![
+                "clear 100 50",
+                "fill #FF0000FF",
+                "circle 30 20 5 true",
+                // Particle crosses the periodic border
+                "circle 2 10 3 false",
+                "circle 102 10 3 false",
+                "line 10 40 90 40",
+            ]
+        );
+
+        // Only active borders are drawn
+        sim.set_draw_params(DrawParams::new(true));
+        let mut recorder = Recorder::default();
+        sim.render(&mut recorder);
+        let lines = recorder
+            .commands
+            .iter()
+            .filter(|c| c.starts_with("line"))
+            .count();
+        assert_eq!(lines, 3);
+    }
+}
@@ -0,0 +1,422 @@
+use std::fmt;
+use std::io::Write;
+
+use super::Renderer;
+use crate::geom::Vec2;
+use crate::particle::RGBA;
+use crate::simulation::Simulation;
+
+const BLACK: RGBA = RGBA {
+    red: 0,
+    green: 0,
+    blue: 0,
+    alpha: 255,
+};
+
+const WHITE: RGBA = RGBA {
+    red: 255,
+    green: 255,
+    blue: 255,
+    alpha: 255,
+};
+
+// RGBA picture, 4 bytes per pixel row by row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RasterImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum RasterError {
+    Png(png::EncodingError),
+    Gif(gif::EncodingError),
+    // Animation needs at least one frame and
+    // GIF frames can't be larger than 65535 pixels
+    InvalidAnimation(String),
+}
+
+impl fmt::Display for RasterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Png(e) => write!(f, "can't encode PNG: {}", e),
+            Self::Gif(e) => write!(f, "can't encode GIF: {}", e),
+            Self::InvalidAnimation(reason) => write!(f, "invalid animation: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for RasterError {}
+
+impl From<png::EncodingError> for RasterError {
+    fn from(e: png::EncodingError) -> Self {
+        RasterError::Png(e)
+    }
+}
+
+impl From<gif::EncodingError> for RasterError {
+    fn from(e: gif::EncodingError) -> Self {
+        RasterError::Gif(e)
+    }
+}
+
+impl RasterImage {
+    pub fn pixel(&self, x: u32, y: u32) -> RGBA {
+        let i = 4 * (y * self.width + x) as usize;
+        let p = &self.pixels[i..i + 4];
+        RGBA {
+            red: p[0],
+            green: p[1],
+            blue: p[2],
+            alpha: p[3],
+        }
+    }
+
+    pub fn write_png<W: Write>(&self, w: W) -> Result<(), RasterError> {
+        let mut writer = png_encoder(w, self.width, self.height).write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        Ok(writer.finish()?)
+    }
+
+    pub fn to_png(&self) -> Result<Vec<u8>, RasterError> {
+        let mut out = Vec::new();
+        self.write_png(&mut out)?;
+        Ok(out)
+    }
+}
+
+// Software backend, that draws anti-aliased shapes into the memory.
+// Coordinates are multiplied by `scale` to get pixels.
+pub struct RasterRenderer {
+    image: RasterImage,
+    scale: f64,
+    background: RGBA,
+    fill: RGBA,
+    stroke: RGBA,
+    // Width of lines and circle outlines in pixels
+    line_width: f64,
+}
+
+impl RasterRenderer {
+    pub fn new(width: u32, height: u32, scale: f64) -> RasterRenderer {
+        RasterRenderer {
+            image: RasterImage {
+                width,
+                height,
+                pixels: vec![255; 4 * (width * height) as usize],
+            },
+            scale,
+            background: WHITE,
+            fill: BLACK,
+            stroke: BLACK,
+            line_width: 1.,
+        }
+    }
+
+    // Renderer, that fits the domain of the simulation.
+    pub fn for_simulation(sim: &Simulation, scale: f64) -> RasterRenderer {
+        RasterRenderer::new(
+            (sim.width() * scale).ceil() as u32,
+            (sim.height() * scale).ceil() as u32,
+            scale,
+        )
+    }
+
+    pub fn with_background(mut self, background: RGBA) -> RasterRenderer {
+        self.background = background;
+        self
+    }
+
+    pub fn with_line_width(mut self, line_width: f64) -> RasterRenderer {
+        self.line_width = line_width;
+        self
+    }
+
+    pub fn image(&self) -> &RasterImage {
+        &self.image
+    }
+
+    // Calls `coverage` for every pixel center in the box and blends
+    // the color with the returned share of the pixel covered by the shape.
+    fn paint<F: Fn(Vec2) -> f64>(&mut self, min: Vec2, max: Vec2, color: RGBA, coverage: F) {
+        let clamp = |v: f64, len: u32| v.max(0.).min(len as f64) as u32;
+        let (x0, x1) = (
+            clamp(min.x.floor(), self.image.width),
+            clamp(max.x.ceil(), self.image.width),
+        );
+        let (y0, y1) = (
+            clamp(min.y.floor(), self.image.height),
+            clamp(max.y.ceil(), self.image.height),
+        );
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let share = coverage(Vec2 {
+                    x: x as f64 + 0.5,
+                    y: y as f64 + 0.5,
+                });
+                if share > 0. {
+                    self.blend(x, y, color, share.min(1.));
+                }
+            }
+        }
+    }
+
+    // Source-over blending of the straight alpha colors.
+    fn blend(&mut self, x: u32, y: u32, color: RGBA, share: f64) {
+        let i = 4 * (y * self.image.width + x) as usize;
+        let dst = &mut self.image.pixels[i..i + 4];
+
+        let src_a = color.alpha as f64 / 255. * share;
+        let dst_a = dst[3] as f64 / 255.;
+        let out_a = src_a + dst_a * (1. - src_a);
+        if out_a <= 0. {
+            return;
+        }
+        let channel = |src: u8, dst: u8| {
+            let value = (src as f64 * src_a + dst as f64 * dst_a * (1. - src_a)) / out_a;
+            value.round() as u8
+        };
+        dst[0] = channel(color.red, dst[0]);
+        dst[1] = channel(color.green, dst[1]);
+        dst[2] = channel(color.blue, dst[2]);
+        dst[3] = (out_a * 255.).round() as u8;
+    }
+}
+
+impl Renderer for RasterRenderer {
+    fn clear(&mut self, width: f64, height: f64) {
+        let background = self.background;
+        let (w, h) = (
+            (width * self.scale).ceil() as u32,
+            (height * self.scale).ceil() as u32,
+        );
+        let image = &mut self.image;
+        for y in 0..h.min(image.height) {
+            for x in 0..w.min(image.width) {
+                let i = 4 * (y * image.width + x) as usize;
+                image.pixels[i..i + 4].copy_from_slice(&[
+                    background.red,
+                    background.green,
+                    background.blue,
+                    background.alpha,
+                ]);
+            }
+        }
+    }
+
+    fn set_fill_color(&mut self, color: RGBA) {
+        self.fill = color;
+    }
+
+    fn set_stroke_color(&mut self, color: RGBA) {
+        self.stroke = color;
+    }
+
+    fn circle(&mut self, center: Vec2, r: f64, fill: bool) {
+        let c = center * self.scale;
+        let r = r * self.scale;
+        let half_width = self.line_width / 2.;
+        let extent = Vec2 {
+            x: r + half_width + 1.,
+            y: r + half_width + 1.,
+        };
+
+        if fill {
+            let color = self.fill;
+            self.paint(c - extent, c + extent, color, |p| r - (p - c).len() + 0.5);
+        }
+        let color = self.stroke;
+        self.paint(c - extent, c + extent, color, |p| {
+            half_width - ((p - c).len() - r).abs() + 0.5
+        });
+    }
+
+    fn line(&mut self, p1: Vec2, p2: Vec2) {
+        let (a, b) = (p1 * self.scale, p2 * self.scale);
+        let half_width = self.line_width / 2.;
+        let d = b - a;
+        let len2 = d * d;
+        let min = Vec2 {
+            x: a.x.min(b.x) - half_width - 1.,
+            y: a.y.min(b.y) - half_width - 1.,
+        };
+        let max = Vec2 {
+            x: a.x.max(b.x) + half_width + 1.,
+            y: a.y.max(b.y) + half_width + 1.,
+        };
+
+        let color = self.stroke;
+        self.paint(min, max, color, |p| {
+            // Distance to the closest point of the segment
+            let t = if len2 > 0. {
+                ((p - a) * d / len2).clamp(0., 1.)
+            } else {
+                0.
+            };
+            half_width - (p - (a + d * t)).len() + 0.5
+        });
+    }
+}
+
+// Advances the simulation by `1 / fps` between the frames
+// and renders `count` frames, the first one is the current state.
+pub fn render_frames(
+    sim: &mut Simulation,
+    renderer: &mut RasterRenderer,
+    fps: u32,
+    count: usize,
+) -> Vec<RasterImage> {
+    let t0 = sim.get_current_tick();
+    (0..count)
+        .map(|i| {
+            // Moments are computed from the start to avoid drift
+            sim.advance_to(t0 + i as f64 / fps as f64);
+            sim.render(renderer);
+            renderer.image().clone()
+        })
+        .collect()
+}
+
+// Looped animated GIF, colors are quantized for every frame.
+pub fn write_gif<W: Write>(w: W, frames: &[RasterImage], fps: u32) -> Result<(), RasterError> {
+    let (width, height) = animation_size(frames)?;
+    let to_u16 = |len: u32| {
+        if len <= u16::MAX as u32 {
+            Ok(len as u16)
+        } else {
+            Err(RasterError::InvalidAnimation(format!(
+                "GIF can't be {} pixels long",
+                len
+            )))
+        }
+    };
+
+    let mut encoder = gif::Encoder::new(w, to_u16(width)?, to_u16(height)?, &[])?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+    // GIF delays are measured in hundredths of a second
+    let delay = (100. / fps as f64).round() as u16;
+    for image in frames {
+        let mut pixels = image.pixels.clone();
+        let mut frame =
+            gif::Frame::from_rgba_speed(image.width as u16, image.height as u16, &mut pixels, 10);
+        frame.delay = delay;
+        encoder.write_frame(&frame)?;
+    }
+    Ok(())
+}
+
+// Looped animated PNG with exact colors.
+pub fn write_apng<W: Write>(w: W, frames: &[RasterImage], fps: u32) -> Result<(), RasterError> {
+    let (width, height) = animation_size(frames)?;
+    let fps = fps.min(u16::MAX as u32) as u16;
+
+    let mut encoder = png_encoder(w, width, height);
+    encoder.set_animated(frames.len() as u32, 0)?;
+    encoder.set_frame_delay(1, fps)?;
+    let mut writer = encoder.write_header()?;
+    for image in frames {
+        writer.write_image_data(&image.pixels)?;
+    }
+    Ok(writer.finish()?)
+}
+
+fn png_encoder<W: Write>(w: W, width: u32, height: u32) -> png::Encoder<'static, W> {
+    let mut encoder = png::Encoder::new(w, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+}
+
+fn animation_size(frames: &[RasterImage]) -> Result<(u32, u32), RasterError> {
+    let first = frames
+        .first()
+        .ok_or_else(|| RasterError::InvalidAnimation("there are no frames".to_owned()))?;
+    if frames
+        .iter()
+        .any(|f| (f.width, f.height) != (first.width, first.height))
+    {
+        return Err(RasterError::InvalidAnimation(
+            "frames have different sizes".to_owned(),
+        ));
+    }
+    Ok((first.width, first.height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compare_floats;
+    use crate::particle::Particle;
+
+    fn simulation() -> Simulation {
+        let mut sim = Simulation::new(40., 20., 60, None);
+        sim.add_particle(&Particle::new(
+            10.,
+            10.,
+            20.,
+            0.,
+            1.,
+            4.,
+            RGBA::from_css_hex("#FF0000"),
+        ));
+        sim
+    }
+
+    #[test]
+    fn test_raster_renderer() {
+        let sim = simulation();
+        let mut renderer = RasterRenderer::for_simulation(&sim, 2.).with_line_width(2.);
+        sim.render(&mut renderer);
+
+        let image = renderer.image();
+        assert_eq!((image.width, image.height), (80, 40));
+        let red = RGBA::new(255, 0, 0, None);
+        assert_eq!(image.pixel(20, 20), red);
+        assert_eq!(image.pixel(60, 20), WHITE);
+        // Borders are not drawn by default, the outline is black
+        assert_eq!(image.pixel(0, 20), WHITE);
+        assert_eq!(image.pixel(20, 12), BLACK);
+        // Edges are anti-aliased
+        let blended = (0..image.height)
+            .flat_map(|y| (0..image.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| ![red, WHITE, BLACK].contains(&image.pixel(x, y)))
+            .count();
+        assert!(blended > 0);
+
+        let png = image.to_png().unwrap();
+        let mut reader = png::Decoder::new(&png[..]).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(pixels, image.pixels);
+    }
+
+    #[test]
+    fn test_raster_animation() {
+        let mut sim = simulation();
+        let mut renderer = RasterRenderer::for_simulation(&sim, 1.);
+        let frames = render_frames(&mut sim, &mut renderer, 10, 5);
+        assert_eq!(frames.len(), 5);
+        compare_floats!(sim.get_current_tick(), 0.4);
+        // Particle moves 2 pixels every frame
+        assert_eq!(frames[0].pixel(10, 10), RGBA::new(255, 0, 0, None));
+        assert_eq!(frames[0].pixel(17, 10), WHITE);
+        assert_eq!(frames[4].pixel(17, 10), RGBA::new(255, 0, 0, None));
+
+        let mut gif = Vec::new();
+        write_gif(&mut gif, &frames, 10).unwrap();
+        assert!(gif.starts_with(b"GIF89a"));
+
+        let mut apng = Vec::new();
+        write_apng(&mut apng, &frames, 10).unwrap();
+        let reader = png::Decoder::new(&apng[..]).read_info().unwrap();
+        let animation = reader.info().animation_control().unwrap();
+        assert_eq!(animation.num_frames, 5);
+
+        assert_eq!(
+            write_gif(&mut gif, &[], 10).unwrap_err().to_string(),
+            "invalid animation: there are no frames"
+        );
+    }
+}
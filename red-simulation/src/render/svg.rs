@@ -0,0 +1,191 @@
+use std::fmt::Write;
+
+use super::{draw_particles, draw_segments, Renderer};
+use crate::geom::Vec2;
+use crate::log;
+use crate::particle::RGBA;
+use crate::simulation::Simulation;
+
+// Vector backend, that collects SVG elements.
+pub struct SvgRenderer {
+    width: f64,
+    height: f64,
+    elements: String,
+    fill: RGBA,
+    stroke: RGBA,
+    // Opacity of the elements drawn next
+    opacity: f64,
+}
+
+impl SvgRenderer {
+    pub fn new(width: f64, height: f64) -> SvgRenderer {
+        SvgRenderer {
+            width,
+            height,
+            elements: String::new(),
+            fill: RGBA::new(0, 0, 0, None),
+            stroke: RGBA::new(0, 0, 0, None),
+            opacity: 1.,
+        }
+    }
+
+    pub fn set_opacity(&mut self, opacity: f64) {
+        self.opacity = opacity;
+    }
+
+    // Complete SVG document with everything drawn so far.
+    pub fn to_svg(&self) -> String {
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" \
+             viewBox=\"0 0 {w} {h}\">\n{elements}</svg>\n",
+            w = self.width,
+            h = self.height,
+            elements = self.elements
+        )
+    }
+
+    fn opacity_attr(&self) -> String {
+        if self.opacity < 1. {
+            format!(" opacity=\"{}\"", self.opacity)
+        } else {
+            String::new()
+        }
+    }
+}
+
+// SVG has no hex colors with alpha, so it's set apart.
+fn paint(attr: &str, color: RGBA) -> String {
+    let mut result = format!(
+        "{}=\"#{:02X}{:02X}{:02X}\"",
+        attr, color.red, color.green, color.blue
+    );
+    if color.alpha < 255 {
+        write!(
+            result,
+            " {}-opacity=\"{}\"",
+            attr,
+            color.alpha as f64 / 255.
+        )
+        .unwrap();
+    }
+    result
+}
+
+impl Renderer for SvgRenderer {
+    fn clear(&mut self, width: f64, height: f64) {
+        self.width = width;
+        self.height = height;
+        self.elements.clear();
+    }
+
+    fn set_fill_color(&mut self, color: RGBA) {
+        self.fill = color;
+    }
+
+    fn set_stroke_color(&mut self, color: RGBA) {
+        self.stroke = color;
+    }
+
+    fn circle(&mut self, center: Vec2, r: f64, fill: bool) {
+        let fill = if fill {
+            paint("fill", self.fill)
+        } else {
+            "fill=\"none\"".to_owned()
+        };
+        writeln!(
+            self.elements,
+            "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" {} {}{}/>",
+            center.x,
+            center.y,
+            r,
+            fill,
+            paint("stroke", self.stroke),
+            self.opacity_attr()
+        )
+        .unwrap();
+    }
+
+    fn line(&mut self, p1: Vec2, p2: Vec2) {
+        writeln!(
+            self.elements,
+            "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" {}{}/>",
+            p1.x,
+            p1.y,
+            p2.x,
+            p2.y,
+            paint("stroke", self.stroke),
+            self.opacity_attr()
+        )
+        .unwrap();
+    }
+}
+
+// Overlays `count` states of the simulation taken every `dt`.
+// Older states fade out, segments are drawn at the last moment.
+// The states are taken from a copy, so the simulation itself,
+// its observers and the game clock aren't affected.
+pub fn time_lapse_svg(sim: &Simulation, dt: f64, count: usize) -> String {
+    if count > 1 && !(dt > 0. && dt.is_finite()) {
+        log!("Warning! Time lapse interval must be positive, got {}.", dt);
+        return sim.to_svg();
+    }
+    let mut copy = Simulation::from_checkpoint(&sim.checkpoint());
+    let mut svg = SvgRenderer::new(copy.width(), copy.height());
+    for i in 0..count {
+        if i > 0 {
+            copy.advance_by(dt);
+        }
+        svg.set_opacity((i + 1) as f64 / count as f64);
+        draw_particles(&copy, &mut svg);
+    }
+    svg.set_opacity(1.);
+    draw_segments(&copy, &mut svg);
+    svg.to_svg()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geom::Segment;
+    use crate::particle::Particle;
+    use crate::simulation::DrawParams;
+
+    #[test]
+    fn test_svg() {
+        let mut sim = Simulation::new(100., 50., 60, Some(DrawParams::new(false)));
+        sim.add_segment(&Segment::new(10., 40., 90., 40.));
+        sim.add_particle(&Particle::new(
+            30.,
+            20.,
+            10.,
+            0.,
+            1.,
+            5.,
+            RGBA::from_css_hex("#FF000080"),
+        ));
+        sim.add_particle(&Particle::new(70., 20., 0., 0., 1., 3., None));
+
+        assert_eq!(
+            sim.to_svg(),
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"100\" height=\"50\" \
+             viewBox=\"0 0 100 50\">\n\
+             <circle cx=\"30\" cy=\"20\" r=\"5\" fill=\"#FF0000\" \
+             fill-opacity=\"0.5019607843137255\" stroke=\"#000000\"/>\n\
+             <circle cx=\"70\" cy=\"20\" r=\"3\" fill=\"none\" stroke=\"#000000\"/>\n\
+             <line x1=\"10\" y1=\"40\" x2=\"90\" y2=\"40\" stroke=\"#000000\"/>\n\
+             </svg>\n"
+        );
+
+        let svg = sim.to_svg_time_lapse(0.5, 4);
+        assert_eq!(sim.get_current_tick(), 0.);
+        assert_eq!(svg.matches("<circle").count(), 8);
+        assert_eq!(svg.matches("<line").count(), 1);
+        assert!(svg.contains("<circle cx=\"30\" cy=\"20\" r=\"5\""));
+        assert!(svg.contains("<circle cx=\"45\" cy=\"20\" r=\"5\""));
+        assert_eq!(svg.matches("opacity=\"0.25\"").count(), 2);
+        assert_eq!(svg.matches("opacity=\"0.75\"").count(), 2);
+
+        // Invalid interval gives the current state only
+        assert_eq!(sim.to_svg_time_lapse(-1., 4), sim.to_svg());
+    }
+}
@@ -0,0 +1,440 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::geom::{Segment, Vec2};
+use crate::particle::Particle;
+use crate::simulation::{DrawParams, DEFAULT_COLLAPSE_TIME};
+
+// Version of the scene format, it's increased on every
+// incompatible change of the format.
+pub const SCENE_VERSION: u32 = 1;
+
+const DEFAULT_TICKS_PER_SEC: u32 = 60;
+
+// Complete description of the simulation's initial state,
+// that can be saved to JSON and loaded back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    pub version: u32,
+    pub width: f64,
+    pub height: f64,
+    #[serde(default = "default_ticks_per_sec")]
+    pub ticks_per_sec: u32,
+    #[serde(default)]
+    pub draw_params: DrawParams,
+    #[serde(default)]
+    pub gravity: Vec2,
+    #[serde(default = "default_restitution")]
+    pub restitution: f64,
+    #[serde(default = "default_collapse_time")]
+    pub collapse_time: f64,
+    // See `Simulation::set_exact_events`
+    #[serde(default)]
+    pub exact_events: bool,
+    #[serde(default)]
+    pub periodic_x: bool,
+    #[serde(default)]
+    pub periodic_y: bool,
+    #[serde(default)]
+    pub particles: Vec<Particle>,
+    // Segments besides the domain borders
+    #[serde(default)]
+    pub segments: Vec<SceneSegment>,
+    // Domain borders, that are thermal walls, in the order:
+    // top, right, bottom and left
+    #[serde(default)]
+    pub border_temperatures: [Option<f64>; 4],
+    #[serde(default)]
+    pub game: Option<GameSetup>,
+}
+
+// Segment's own parameters, everything else is derived from them.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SceneSegment {
+    pub p1: Vec2,
+    pub p2: Vec2,
+    #[serde(default)]
+    pub restitution: Option<f64>,
+    #[serde(default)]
+    pub velocity: Vec2,
+    #[serde(default)]
+    pub temperature: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GameSetup {
+    // Player's particle, it's added when the game starts
+    pub player: Particle,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SceneError {
+    // Scene is not a valid JSON or doesn't match the format
+    Parse(String),
+    UnsupportedVersion(u32),
+    InvalidDomain { width: f64, height: f64 },
+    InvalidTicksPerSec,
+    InvalidParameter { name: &'static str, reason: String },
+    InvalidParticle { index: usize, reason: String },
+    InvalidSegment { index: usize, reason: String },
+    InvalidPlayer(String),
+    // Particle overlaps other particle or segment
+    ParticleCollides { index: usize },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Parse(reason) => write!(f, "can't parse the scene: {}", reason),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "scene version {} is not supported, expected {}",
+                version, SCENE_VERSION
+            ),
+            Self::InvalidDomain { width, height } => write!(
+                f,
+                "domain size must be positive, got {} x {}",
+                width, height
+            ),
+            Self::InvalidTicksPerSec => write!(f, "ticks_per_sec must be positive"),
+            Self::InvalidParameter { name, reason } => write!(f, "{}: {}", name, reason),
+            Self::InvalidParticle { index, reason } => {
+                write!(f, "particle #{}: {}", index, reason)
+            }
+            Self::InvalidSegment { index, reason } => write!(f, "segment #{}: {}", index, reason),
+            Self::InvalidPlayer(reason) => write!(f, "player's particle: {}", reason),
+            Self::ParticleCollides { index } => write!(
+                f,
+                "particle #{} overlaps another particle or segment",
+                index
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<serde_json::Error> for SceneError {
+    fn from(e: serde_json::Error) -> Self {
+        SceneError::Parse(e.to_string())
+    }
+}
+
+impl Scene {
+    pub fn new(width: f64, height: f64) -> Scene {
+        Scene {
+            version: SCENE_VERSION,
+            width,
+            height,
+            ticks_per_sec: DEFAULT_TICKS_PER_SEC,
+            draw_params: Default::default(),
+            gravity: Default::default(),
+            restitution: default_restitution(),
+            collapse_time: DEFAULT_COLLAPSE_TIME,
+            exact_events: false,
+            periodic_x: false,
+            periodic_y: false,
+            particles: Vec::new(),
+            segments: Vec::new(),
+            border_temperatures: [None; 4],
+            game: None,
+        }
+    }
+
+    // Parses and validates the scene.
+    pub fn from_json(json: &str) -> Result<Scene, SceneError> {
+        let scene: Scene = serde_json::from_str(json)?;
+        scene.validate()?;
+        Ok(scene)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    // Checks every value of the scene on its own. Overlapping
+    // particles are found only when the simulation is built.
+    pub fn validate(&self) -> Result<(), SceneError> {
+        if self.version != SCENE_VERSION {
+            return Err(SceneError::UnsupportedVersion(self.version));
+        }
+        if !(is_positive(self.width) && is_positive(self.height)) {
+            return Err(SceneError::InvalidDomain {
+                width: self.width,
+                height: self.height,
+            });
+        }
+        if self.ticks_per_sec == 0 {
+            return Err(SceneError::InvalidTicksPerSec);
+        }
+        if !(self.gravity.x.is_finite() && self.gravity.y.is_finite()) {
+            return Err(invalid_parameter("gravity", "must be finite"));
+        }
+        check_restitution(Some(self.restitution))
+            .map_err(|reason| invalid_parameter("restitution", &reason))?;
+        if !(self.collapse_time >= 0. && self.collapse_time.is_finite()) {
+            return Err(invalid_parameter("collapse_time", "must be non-negative"));
+        }
+
+        for temperature in &self.border_temperatures {
+            check_temperature(*temperature)
+                .map_err(|reason| invalid_parameter("border_temperatures", &reason))?;
+        }
+
+        for (index, particle) in self.particles.iter().enumerate() {
+            self.check_particle(particle)
+                .map_err(|reason| SceneError::InvalidParticle { index, reason })?;
+        }
+        let r_max = self
+            .particles
+            .iter()
+            .chain(self.game.as_ref().map(|game| &game.player))
+            .map(|p| p.r)
+            .fold(0., f64::max);
+        for (index, segment) in self.segments.iter().enumerate() {
+            check_segment(segment)
+                .and_then(|_| self.check_periodic_gap(segment, r_max))
+                .map_err(|reason| SceneError::InvalidSegment { index, reason })?;
+        }
+        if let Some(game) = &self.game {
+            self.check_particle(&game.player)
+                .map_err(SceneError::InvalidPlayer)?;
+        }
+        Ok(())
+    }
+
+    // Particle that straddles the periodic border hits segments only by
+    // its own side, so segments near the border would let its image pass.
+    fn check_periodic_gap(&self, s: &SceneSegment, r_max: f64) -> Result<(), String> {
+        let near = |a: f64, b: f64, len: f64| a.min(b) < r_max || a.max(b) > len - r_max;
+        if (self.periodic_x && near(s.p1.x, s.p2.x, self.width))
+            || (self.periodic_y && near(s.p1.y, s.p2.y, self.height))
+        {
+            return Err(format!(
+                "must be farther than the largest particle radius {} from periodic borders",
+                r_max
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_particle(&self, p: &Particle) -> Result<(), String> {
+        if !(p.pos.x.is_finite() && p.pos.y.is_finite()) {
+            return Err(format!("position must be finite, got {:?}", p.pos));
+        }
+        if !(p.v.x.is_finite() && p.v.y.is_finite()) {
+            return Err(format!("velocity must be finite, got {:?}", p.v));
+        }
+        if !is_positive(p.r) {
+            return Err(format!("radius must be positive, got {}", p.r));
+        }
+        if !p.fixed && !is_positive(p.m) {
+            return Err(format!("mass must be positive, got {}", p.m));
+        }
+        check_restitution(p.restitution)?;
+
+        // Particles may cross periodic borders only
+        let inside =
+            |pos: f64, len: f64, periodic: bool| periodic || (pos - p.r >= 0. && pos + p.r <= len);
+        if !inside(p.pos.x, self.width, self.periodic_x)
+            || !inside(p.pos.y, self.height, self.periodic_y)
+        {
+            return Err(format!("lies outside of the domain at {:?}", p.pos));
+        }
+        Ok(())
+    }
+}
+
+impl From<&Segment> for SceneSegment {
+    fn from(segment: &Segment) -> Self {
+        SceneSegment {
+            p1: segment.p1,
+            p2: segment.p2,
+            restitution: segment.restitution,
+            velocity: segment.velocity,
+            temperature: segment.temperature,
+        }
+    }
+}
+
+impl From<&SceneSegment> for Segment {
+    fn from(segment: &SceneSegment) -> Self {
+        let mut result = Segment::from_points(segment.p1, segment.p2)
+            .with_velocity(segment.velocity.x, segment.velocity.y);
+        result.restitution = segment.restitution;
+        result.temperature = segment.temperature;
+        result
+    }
+}
+
+fn check_segment(s: &SceneSegment) -> Result<(), String> {
+    let finite = |p: Vec2| p.x.is_finite() && p.y.is_finite();
+
+    if !(finite(s.p1) && finite(s.p2) && finite(s.velocity)) {
+        return Err("coordinates and velocity must be finite".to_owned());
+    }
+    if s.p1 == s.p2 {
+        return Err(format!("ends must be different points, got {:?}", s.p1));
+    }
+    check_restitution(s.restitution)?;
+    check_temperature(s.temperature)
+}
+
+fn check_restitution(restitution: Option<f64>) -> Result<(), String> {
+    match restitution {
+        Some(e) if !(0. ..=1.).contains(&e) => Err(format!(
+            "coefficient of restitution must lie in 0..1, got {}",
+            e
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn check_temperature(temperature: Option<f64>) -> Result<(), String> {
+    match temperature {
+        Some(t) if !is_positive(t) => Err(format!("temperature must be positive, got {}", t)),
+        _ => Ok(()),
+    }
+}
+
+#[inline]
+fn is_positive(value: f64) -> bool {
+    value > 0. && value.is_finite()
+}
+
+fn invalid_parameter(name: &'static str, reason: &str) -> SceneError {
+    SceneError::InvalidParameter {
+        name,
+        reason: reason.to_owned(),
+    }
+}
+
+fn default_ticks_per_sec() -> u32 {
+    DEFAULT_TICKS_PER_SEC
+}
+
+fn default_restitution() -> f64 {
+    1.
+}
+
+fn default_collapse_time() -> f64 {
+    DEFAULT_COLLAPSE_TIME
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Simulation;
+
+    fn scene() -> Scene {
+        let mut scene = Scene::new(100., 80.);
+        scene.gravity = Vec2 { x: 0., y: 10. };
+        scene.particles = vec![
+            Particle::new(20., 20., 5., 0., 1., 5., None),
+            Particle::new(50., 50., 0., 0., 1., 3., None).as_fixed(),
+        ];
+        scene.segments = vec![SceneSegment {
+            p1: Vec2 { x: 70., y: 10. },
+            p2: Vec2 { x: 70., y: 70. },
+            restitution: Some(0.5),
+            velocity: Vec2 { x: -1., y: 0. },
+            temperature: Some(2.),
+        }];
+        scene
+    }
+
+    #[test]
+    fn test_scene_json() {
+        let scene = scene();
+        assert_eq!(Scene::from_json(&scene.to_json()).unwrap(), scene);
+
+        // Everything besides the version and the domain is optional
+        let minimal = Scene::from_json(r#"{"version": 1, "width": 100, "height": 80}"#).unwrap();
+        assert_eq!(minimal, Scene::new(100., 80.));
+
+        let particle = r#"{"version": 1, "width": 10, "height": 10,
+            "particles": [{"pos": {"x": 5, "y": 5}, "v": {"x": 1, "y": 0}, "m": 1, "r": 1}]}"#;
+        let loaded = Scene::from_json(particle).unwrap();
+        assert_eq!(
+            loaded.particles,
+            vec![Particle::new(5., 5., 1., 0., 1., 1., None)]
+        );
+    }
+
+    #[test]
+    fn test_scene_warnings() {
+        // Warnings don't need the browser console,
+        // so the native render binary can load such scenes
+        let mut scene = Scene::new(10., 10.);
+        scene.periodic_x = true;
+        scene.particles = vec![Particle::new(5., 5., 1., 0., 1., 2., None)];
+        let mut sim = Simulation::from_scene(&scene).unwrap();
+        sim.tick();
+    }
+
+    #[test]
+    fn test_scene_validation() {
+        let error = |json: &str| Scene::from_json(json).unwrap_err().to_string();
+
+        assert_eq!(
+            error(r#"{"version": 2, "width": 100, "height": 80}"#),
+            "scene version 2 is not supported, expected 1"
+        );
+        assert_eq!(
+            error(r#"{"version": 1, "width": 0, "height": 80}"#),
+            "domain size must be positive, got 0 x 80"
+        );
+        assert!(error(r#"{"version": 1, "width": 100}"#).starts_with("can't parse the scene"));
+
+        let mut scene = scene();
+        scene.particles[0].r = -1.;
+        assert_eq!(
+            scene.validate().unwrap_err().to_string(),
+            "particle #0: radius must be positive, got -1"
+        );
+
+        let mut scene = self::scene();
+        scene.particles[0].pos.x = 98.;
+        assert!(matches!(
+            scene.validate(),
+            Err(SceneError::InvalidParticle { index: 0, .. })
+        ));
+        // Unless the axis is periodic
+        scene.periodic_x = true;
+        assert_eq!(scene.validate(), Ok(()));
+
+        let mut scene = self::scene();
+        scene.segments[0].restitution = Some(1.5);
+        assert_eq!(
+            scene.validate().unwrap_err().to_string(),
+            "segment #0: coefficient of restitution must lie in 0..1, got 1.5"
+        );
+
+        // Particle's image would pass the segment near the periodic border
+        let mut scene = self::scene();
+        scene.periodic_y = true;
+        assert_eq!(scene.validate(), Ok(()));
+        scene.segments[0].p2.y = 77.;
+        assert_eq!(
+            scene.validate().unwrap_err().to_string(),
+            "segment #0: must be farther than the largest particle radius 5 from periodic borders"
+        );
+
+        let mut scene = self::scene();
+        scene.segments[0].temperature = Some(0.);
+        assert_eq!(
+            scene.validate().unwrap_err().to_string(),
+            "segment #0: temperature must be positive, got 0"
+        );
+        let mut scene = self::scene();
+        scene.border_temperatures[3] = Some(-1.);
+        assert!(matches!(
+            scene.validate(),
+            Err(SceneError::InvalidParameter {
+                name: "border_temperatures",
+                ..
+            })
+        ));
+    }
+}
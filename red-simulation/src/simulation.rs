@@ -1,31 +1,96 @@
 use std::collections::{BinaryHeap, HashSet};
 
+use js_sys::{Float64Array, Uint8Array};
 use wasm_bindgen::prelude::*;
 
 use serde::{Deserialize, Serialize};
 use web_sys::CanvasRenderingContext2d;
 
+use super::checkpoint::{Checkpoint, CheckpointError, GameCheckpoint, CHECKPOINT_VERSION};
 use super::collisions::{pvp, pvs, Collision, CollisionEvent, CollisionPair};
+use super::diffusion::{MsdCurve, MsdTracker};
+use super::distribution::{Histogram, Maxwell};
+use super::event_log::{EventLog, LoggedEvent};
+use super::free_path::{Enskog, FreePathTracker, FreePaths};
 use super::game::GameParams;
 use super::geom::{Segment, Vec2};
-use super::particle::Particle;
+use super::grid::Grid;
+use super::observer::{CollisionObserver, CollisionReport, Impact, JsCollisionObserver};
+use super::particle::{Particle, ParticleId, ParticleStore, RGBA};
+use super::pressure::{SegmentLoad, WallMonitor};
+use super::probes::{Gate, Region, RegionStats};
+use super::random::Random;
+use super::rdf::{RadialDistribution, RdfSampler};
+use super::render::{self, time_lapse_svg, CanvasRenderer, Renderer, SvgRenderer};
+use super::scene::{GameSetup, Scene, SceneError, SceneSegment, SCENE_VERSION};
+use super::state_arrays::StateArrays;
+use super::stats::{Moments, Stats};
+use super::trajectory::{Frame, ParticleState, Trajectory, TrajectoryHeader, TrajectoryRecorder};
+use super::utils::{from_js_value, to_js_value};
 
 use crate::log;
 
+// Particles that have collided less than this time ago
+// bounce elastically, see `Simulation::set_collapse_time`.
+pub(crate) const DEFAULT_COLLAPSE_TIME: f64 = 1e-5;
+
+// Time over which the pressure on walls is averaged by default.
+pub(crate) const DEFAULT_PRESSURE_WINDOW: f64 = 1.;
+
+// Seed of the random numbers, until another one is set.
+const DEFAULT_SEED: u64 = 0x5eed;
+
 #[wasm_bindgen]
 pub struct Simulation {
     w: f64,
     h: f64,
     initialized: bool,
     segments: Vec<Segment>,
-    particles: Vec<Particle>,
+    // Indexes of moving segments, they are not registered in the grid
+    moving_segments: Vec<usize>,
+    particles: ParticleStore,
     events: BinaryHeap<CollisionEvent>,
+    // Collisions that happened at the current moment
+    collisions_happend: HashSet<CollisionPair>,
+    grid: Grid,
     t: f64,
     ticks_per_sec: u32,
     tick_time: f64,
+    // Uniform acceleration applied to every particle
+    gravity: Vec2,
+    // Axes, where the particle leaving the domain
+    // appears on its opposite side
+    periodic_x: bool,
+    periodic_y: bool,
+    // Default coefficient of restitution
+    restitution: f64,
+    collapse_time: f64,
+    // Time of the last collision for every particle slot
+    last_collisions: Vec<f64>,
+    // Whether events are resolved at their own moments, see `set_exact_events`
+    exact_events: bool,
+    // Work done on particles by moving segments
+    wall_work: f64,
+    // Momentum delivered to segments by particles
+    walls: WallMonitor,
+    gates: Vec<Gate>,
+    // Source of the velocities given by thermal walls
+    random: Random,
 
     game_params: Option<GameParams>,
+    // Player's particle from the loaded scene, see `start_game`
+    scene_player: Option<Particle>,
     draw_params: DrawParams,
+    observers: Vec<Box<dyn CollisionObserver>>,
+    recorder: Option<TrajectoryRecorder>,
+    msd: Option<MsdTracker>,
+    free_paths: Option<FreePathTracker>,
+    rdf: Option<RdfSampler>,
+    event_log: Option<EventLog>,
+    // Buffers behind the typed array views, see `update_state_arrays`
+    state_arrays: StateArrays,
+    // Observables, that are kept up to date since the initialization
+    moments: Moments,
 }
 
 #[wasm_bindgen]
@@ -47,80 +112,446 @@ impl Simulation {
             h: height,
             initialized: false,
             segments: Segment::create_rectangle_domain(Vec2 { x: 0., y: 0. }, width, height),
-            particles: Vec::new(),
+            moving_segments: Vec::new(),
+            particles: ParticleStore::new(),
             events: BinaryHeap::new(),
+            collisions_happend: HashSet::new(),
+            grid: Grid::new(width, height, 0.),
             t: 0.,
             ticks_per_sec: ticks_per_sec,
             tick_time: 1. / (ticks_per_sec as f64),
+            gravity: Vec2 { x: 0., y: 0. },
+            periodic_x: false,
+            periodic_y: false,
+            restitution: 1.,
+            collapse_time: DEFAULT_COLLAPSE_TIME,
+            last_collisions: Vec::new(),
+            exact_events: false,
+            wall_work: 0.,
+            walls: WallMonitor::new(DEFAULT_PRESSURE_WINDOW),
+            gates: Vec::new(),
+            random: Random::new(DEFAULT_SEED),
             game_params: None,
+            scene_player: None,
             draw_params,
+            observers: Vec::new(),
+            recorder: None,
+            msd: None,
+            free_paths: None,
+            rdf: None,
+            event_log: None,
+            state_arrays: StateArrays::default(),
+            moments: Moments::default(),
         }
     }
 
     // Main function that represents one iteration of the simulation.
     pub fn tick(&mut self) {
+        self.advance_to(self.t + self.tick_time);
+    }
+
+    // Processes all the events up to the moment `t` and
+    // moves the system to this moment.
+    pub fn advance_to(&mut self, t: f64) {
         if !self.initialized {
             self.init();
         }
 
         self.explicitly_check_player_particle();
 
-        let mut collisions_happend: HashSet<CollisionPair> = HashSet::new();
-        let target_time = self.t + self.tick_time;
-
         while let Some(event) = self.events.peek() {
-            if event.t <= target_time {
+            if event.t <= t {
                 let event = self.events.pop().unwrap();
-                let collision_pair: CollisionPair = event.collision.into();
+                self.process_event(&event);
+            } else {
+                break;
+            }
+        }
 
-                // Check whether this collision has already happened
-                // during the current tick. This check protects us from
-                // infinite loop in case of the multi-particle collision.
-                if collisions_happend.contains(&collision_pair) {
-                    continue;
-                }
+        self.record_until(t);
+        self.mv(t);
+    }
+
+    pub fn advance_by(&mut self, dt: f64) {
+        self.advance_to(self.t + dt);
+    }
+
+    pub fn get_restitution(&self) -> f64 {
+        self.restitution
+    }
+
+    // Sets the coefficient of restitution for particles and segments
+    // that don't have their own one. 1 means perfectly elastic collisions.
+    pub fn set_restitution(&mut self, restitution: f64) {
+        self.restitution = restitution;
+    }
+
+    pub fn get_collapse_time(&self) -> f64 {
+        self.collapse_time
+    }
+
+    // Collisions of particles, that have collided less than `collapse_time`
+    // ago, are perfectly elastic. Larger values make inelastic systems
+    // more stable, but less accurate.
+    pub fn set_collapse_time(&mut self, collapse_time: f64) {
+        self.collapse_time = collapse_time;
+    }
+
+    pub fn get_gravity(&self) -> Vec2 {
+        self.gravity
+    }
+
+    // Sets uniform acceleration applied to every particle.
+    // Inelastic scenes with gravity usually need larger `collapse_time`,
+    // since resting particles keep bouncing with the period of it.
+    pub fn set_gravity(&mut self, x: f64, y: f64) {
+        if let Some(tracker) = &mut self.free_paths {
+            for (i, p) in self.particles.iter() {
+                tracker.turned(self.particles.id(i), self.t, p.v, self.gravity);
+            }
+        }
+        self.gravity = Vec2 { x, y };
+        self.initialized = false;
+        self.log_event(LoggedEvent::GravityChanged {
+            t: self.t,
+            gravity: self.gravity,
+        });
+    }
+
+    pub fn is_periodic_x(&self) -> bool {
+        self.periodic_x
+    }
+
+    pub fn is_periodic_y(&self) -> bool {
+        self.periodic_y
+    }
+
+    // Makes boundaries periodic along the chosen axes, corresponding
+    // domain borders are switched off. Segments must stay farther than
+    // the largest particle radius from periodic borders, because the
+    // particle's image on the other side doesn't hit segments. The domain
+    // must be at least 3 particle diameters long along the periodic axis.
+    pub fn set_periodic(&mut self, x: bool, y: bool) {
+        self.periodic_x = x;
+        self.periodic_y = y;
+        self.initialized = false;
+    }
+
+    pub fn get_tick_time(&self) -> f64 {
+        self.tick_time
+    }
+
+    // Changes the time step used by `tick`.
+    // Not available in game mode, because scores depend on it.
+    pub fn set_tick_time(&mut self, tick_time: f64) {
+        if self.game_params.is_some() {
+            log!("Warning! Time step can't be changed in game mode.");
+        } else if tick_time > 0. {
+            self.tick_time = tick_time;
+        } else {
+            log!("Warning! Time step must be positive, got {}.", tick_time);
+        }
+    }
+
+    pub fn get_exact_events(&self) -> bool {
+        self.exact_events
+    }
+
+    // By default the event is resolved while the system is still at the
+    // moment of the previous event, and then the system is moved to the
+    // event's moment. That's how the simulation has always worked, but
+    // collisions happen slightly early and the result depends on how the
+    // time is split into ticks. Exact events move the system to the event's
+    // moment first. Not available in game mode, because scores depend on it.
+    pub fn set_exact_events(&mut self, exact: bool) {
+        if self.game_params.is_some() {
+            log!("Warning! Event resolution can't be changed in game mode.");
+        } else {
+            self.exact_events = exact;
+        }
+    }
+
+    pub fn set_ticks_per_sec(&mut self, ticks_per_sec: u32) {
+        if self.game_params.is_some() {
+            log!("Warning! Time step can't be changed in game mode.");
+        } else if ticks_per_sec > 0 {
+            self.ticks_per_sec = ticks_per_sec;
+            self.tick_time = 1. / (ticks_per_sec as f64);
+        } else {
+            log!("Warning! Ticks per second must be positive.");
+        }
+    }
+
+    // Processes events until the next collision happens.
+    // Returns the time of the collision, or nothing if
+    // no collision is going to happen at all.
+    pub fn step_event(&mut self) -> Option<f64> {
+        if !self.initialized {
+            self.init();
+        }
+
+        self.explicitly_check_player_particle();
+
+        while let Some(event) = self.events.pop() {
+            if self.process_event(&event) {
+                return Some(event.t);
+            }
+        }
+        None
+    }
+
+    // Handles single event from the queue.
+    // Returns `true` if it was an actual collision.
+    fn process_event(&mut self, event: &CollisionEvent) -> bool {
+        let collision_pair: CollisionPair = event.collision.into();
+
+        if self.exact_events {
+            self.record_until(event.t);
+            self.mv(event.t);
+        }
+
+        // Check whether this collision has already happened
+        // at the current moment. This check protects us from
+        // infinite loop in case of the multi-particle collision.
+        if self.collisions_happend.contains(&collision_pair) {
+            return false;
+        }
+
+        let collided = match event.collision {
+            Collision::ParticleVsParticle {
+                p1,
+                p2,
+                p1_cc,
+                p2_cc,
+            } => {
+                if self.is_actual(p1, p1_cc) && self.is_actual(p2, p2_cc) {
+                    let left = self.particles[p1.index()];
+                    let right = self.particles[p2.index()];
+                    let e = self.effective_restitution(
+                        &[left.restitution, right.restitution],
+                        &[p1.index(), p2.index()],
+                    );
+                    // Particles may touch each other through the periodic border
+                    let (n_left, mut n_right) =
+                        pvp::inelastic_collision(&left, &self.image(&right, &left), e);
+                    n_right.pos = right.pos;
 
-                match event.collision {
-                    Collision::ParticleVsParticle {
-                        p1,
-                        p2,
-                        p1_cc,
-                        p2_cc,
-                    } => {
-                        let left = self.particles[p1];
-                        let right = self.particles[p2];
-
-                        if left.collisions_count == p1_cc && right.collisions_count == p2_cc {
-                            let (n_left, n_right) = pvp::collision(&left, &right);
-
-                            self.update_particle(p1, n_left, &collision_pair);
-                            self.update_particle(p2, n_right, &collision_pair);
-                            collisions_happend.insert(collision_pair);
+                    // Fixed particles are obstacles, like segments: bounces
+                    // off them don't end the flight, since the theory
+                    // counts only collisions between moving particles
+                    if let Some(tracker) = &mut self.free_paths {
+                        let obstacle = left.fixed || right.fixed;
+                        for (p, particle) in [(p1, &left), (p2, &right)].iter() {
+                            if particle.fixed {
+                                continue;
+                            }
+                            if obstacle {
+                                tracker.turned(*p, self.t, particle.v, self.gravity);
+                            } else {
+                                tracker.collided(*p, self.t, particle.v, self.gravity);
+                            }
                         }
                     }
-                    Collision::ParticleVsSegment { p, s, p_cc } => {
-                        let particle = self.particles[p];
 
-                        if particle.collisions_count == p_cc {
-                            let segment = self.segments[s];
-                            let n_particle = pvs::collision(&particle, &segment);
+                    // Fixed particles don't change, so their events stay actual
+                    if !left.fixed {
+                        self.update_particle(p1.index(), n_left, &collision_pair);
+                    }
+                    if !right.fixed {
+                        self.update_particle(p2.index(), n_right, &collision_pair);
+                    }
+                    self.collisions_happend.insert(collision_pair);
 
-                            self.update_particle(p, n_particle, &collision_pair);
-                            collisions_happend.insert(collision_pair);
-                        }
+                    if self.is_observed() {
+                        let p1 = self.impact(p1, &left);
+                        let p2 = self.impact(p2, &right);
+                        let impulse = if left.fixed {
+                            p2.dv() * -right.m
+                        } else {
+                            p1.dv() * left.m
+                        };
+                        self.notify(CollisionReport::ParticleVsParticle {
+                            t: event.t,
+                            impulse,
+                            p1,
+                            p2,
+                        });
                     }
+                    true
+                } else {
+                    false
                 }
+            }
+            Collision::ParticleVsSegment { p, s, p_cc } => {
+                if self.is_actual(p, p_cc) {
+                    let particle = self.particles[p.index()];
+                    let segment = self.segments[s];
+                    let n_particle = if let Some(temperature) = segment.temperature {
+                        pvs::thermal_collision(&particle, &segment, temperature, &mut self.random)
+                    } else {
+                        let e = self.effective_restitution(
+                            &[particle.restitution, segment.restitution],
+                            &[p.index()],
+                        );
+                        pvs::inelastic_collision(&particle, &segment, e)
+                    };
+                    let n_particle = self.keep_off_segment(n_particle, &segment);
 
-                if self.t < event.t {
-                    collisions_happend.clear();
-                    self.mv(event.t);
+                    // Work is done only by the moving segment: W = J * u,
+                    // the rest of the energy change is the segment's heat
+                    let work = (n_particle.v - particle.v) * segment.velocity * particle.m;
+                    let energy = 0.5 * particle.m * (n_particle.v.len_sqr() - particle.v.len_sqr());
+                    self.wall_work += work;
+                    // Segment gets the opposite impulse
+                    self.walls.hit(
+                        event.t,
+                        s,
+                        (particle.v - n_particle.v) * particle.m,
+                        segment.n,
+                        energy - work,
+                    );
+
+                    if let Some(tracker) = &mut self.free_paths {
+                        tracker.turned(p, self.t, particle.v, self.gravity);
+                    }
+
+                    self.update_particle(p.index(), n_particle, &collision_pair);
+                    self.collisions_happend.insert(collision_pair);
+
+                    if self.is_observed() {
+                        let p = self.impact(p, &particle);
+                        self.notify(CollisionReport::ParticleVsSegment {
+                            t: event.t,
+                            impulse: p.dv() * particle.m,
+                            p,
+                            s,
+                        });
+                    }
+                    true
+                } else {
+                    false
                 }
-            } else {
-                break;
             }
+            Collision::CellCrossing { p, cell, p_cc } => {
+                if self.is_actual(p, p_cc) {
+                    self.cross_cell(p.index(), cell);
+                    self.collisions_happend.insert(collision_pair);
+                }
+                // Crossing is not a collision
+                false
+            }
+            Collision::BoundaryCrossing { p, cell, p_cc } => {
+                if self.is_actual(p, p_cc) {
+                    self.cross_boundary(p.index(), cell);
+                    self.collisions_happend.insert(collision_pair);
+                }
+                false
+            }
+            Collision::GateCrossing { p, gate, p_cc } => {
+                if self.is_actual(p, p_cc) && gate < self.gates.len() {
+                    // In the default order the particle is behind the gate yet
+                    let mut particle = self.particles[p.index()];
+                    particle.mv_accelerated(event.t - self.t, self.acceleration(&particle));
+                    if self.gates[gate].cross(&particle) {
+                        self.calculate_gate_event(p.index(), gate, Some(event.t));
+                    }
+                    self.collisions_happend.insert(collision_pair);
+                }
+                false
+            }
+        };
+
+        // In the default order the system is moved to the moment of
+        // the event after it's resolved, see `set_exact_events`
+        self.record_until(event.t);
+        self.mv(event.t);
+        collided
+    }
+
+    // The smallest of participants' coefficients of restitution.
+    // Particles that have collided less than `collapse_time` ago
+    // bounce elastically, this protects the simulation from the
+    // inelastic collapse, where the infinite amount of collisions
+    // happens in a finite time (so-called TC model).
+    fn effective_restitution(&self, coefficients: &[Option<f64>], slots: &[usize]) -> f64 {
+        if slots
+            .iter()
+            .any(|&i| self.t - self.last_collisions[i] < self.collapse_time)
+        {
+            return 1.;
+        }
+
+        coefficients
+            .iter()
+            .map(|e| e.unwrap_or(self.restitution))
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    #[inline]
+    fn acceleration(&self, particle: &Particle) -> Vec2 {
+        if particle.fixed {
+            Vec2 { x: 0., y: 0. }
+        } else {
+            self.gravity
+        }
+    }
+
+    // Particle, that is pressed to the segment by the acceleration, would
+    // sink into it after a too weak bounce. Minimal bounce speed makes
+    // it jump off the segment at least once in `collapse_time`.
+    fn keep_off_segment(&self, mut particle: Particle, segment: &Segment) -> Particle {
+        let outer_n = if (particle.pos - segment.p1) * segment.n < 0. {
+            segment.n * -1.
+        } else {
+            segment.n
+        };
+        let an = self.acceleration(&particle) * outer_n;
+
+        if an < 0. {
+            let min_vn = -an * self.collapse_time / 2.;
+            let vn = (particle.v - segment.velocity) * outer_n;
+            if vn < min_vn {
+                particle.v += outer_n * (min_vn - vn);
+            }
+        }
+        particle
+    }
+
+    #[inline]
+    fn impact(&self, id: ParticleId, before: &Particle) -> Impact {
+        Impact {
+            id,
+            v_before: before.v,
+            v_after: self.particles[id.index()].v,
+        }
+    }
+
+    // Whether anyone needs collision reports.
+    fn is_observed(&self) -> bool {
+        !self.observers.is_empty() || self.event_log.is_some()
+    }
+
+    fn notify(&mut self, report: CollisionReport) {
+        // Replay needs the moment, when the velocities change. In the
+        // default order it's earlier than the collision, see `set_exact_events`
+        let mut logged = report;
+        match &mut logged {
+            CollisionReport::ParticleVsParticle { t, .. }
+            | CollisionReport::ParticleVsSegment { t, .. } => *t = self.t,
+        }
+        self.log_event((&logged).into());
+        for observer in self.observers.iter_mut() {
+            observer.on_collision(&report);
         }
+    }
 
-        self.mv(target_time);
+    // Checks that the event's particle still exists and
+    // hasn't changed its trajectory since the event was predicted.
+    #[inline]
+    fn is_actual(&self, id: ParticleId, cc: u64) -> bool {
+        self.particles
+            .get(id)
+            .is_some_and(|p| p.collisions_count == cc)
     }
 
     // Checks whether the player's particle has collided.
@@ -128,7 +559,7 @@ impl Simulation {
     #[inline]
     fn explicitly_check_player_particle(&mut self) {
         if let Some(gp) = &self.game_params {
-            if self.is_collission(&self.particles[gp.p_particle]) {
+            if self.is_collission(&self.particles[gp.p_particle.index()]) {
                 self.game_params
                     .as_mut()
                     .unwrap()
@@ -141,38 +572,114 @@ impl Simulation {
     // in case of any changes in parameters or particles.
     fn init(&mut self) {
         self.events.clear();
-        for l in 0..self.particles.len() {
-            self.calculate_particle_events(l);
+        self.last_collisions
+            .resize(self.particles.slots(), f64::NEG_INFINITY);
+        self.build_grid();
+        for l in 0..self.particles.slots() {
+            if self.particles.is_alive(l) {
+                self.calculate_particle_events(l);
+            }
         }
+        self.moments = Moments::new(self.t, self.particles.iter().map(|(_, p)| p));
         self.initialized = true;
     }
 
+    // Distributes particles and segments over the grid cells.
+    // Cell size is chosen to fit the largest particle.
+    fn build_grid(&mut self) {
+        let max_r = self
+            .particles
+            .iter()
+            .fold(0., |acc: f64, (_, p)| acc.max(p.r));
+        self.grid =
+            Grid::new_periodic(self.w, self.h, 2. * max_r, self.periodic_x, self.periodic_y);
+        if self.grid.cell_size() < 2. * max_r {
+            log!("Warning! Domain is too small for periodic boundaries.");
+        }
+
+        for (p, particle) in self.particles.iter() {
+            self.grid.insert_particle(p, particle);
+        }
+        self.moving_segments.clear();
+        for (s, segment) in self.segments.iter().enumerate() {
+            if !self.is_segment_active(s) {
+                continue;
+            }
+            if segment.is_moving() {
+                self.moving_segments.push(s);
+            } else {
+                self.grid.insert_segment(s, segment);
+            }
+        }
+    }
+
     // Recalculates events for the specified
     // particle with index `l`.
     fn calculate_particle_events(&mut self, l: usize) {
+        let cells: Vec<usize> = self.grid.neighbours(self.grid.particle_cell(l)).collect();
+        self.calculate_cells_events(l, &cells);
+        // Moving segments may be reached from any cell
+        self.calculate_segments_events(l, self.moving_segments.clone());
+        self.calculate_cell_crossing_event(l);
+        for gate in 0..self.gates.len() {
+            self.calculate_gate_event(l, gate, None);
+        }
+    }
+
+    // Calculates collisions of the particle with index `l` against
+    // particles and segments registered in the `cells`.
+    fn calculate_cells_events(&mut self, l: usize, cells: &[usize]) {
         let left = self.particles[l];
+        let left_id = self.particles.id(l);
+        let mut segments = Vec::new();
 
-        for (r, right) in self.particles.iter().enumerate() {
-            if let Some(hit_time) = pvp::time_to_hit(&left, &right) {
-                self.events.push(CollisionEvent {
-                    t: self.t + hit_time,
-                    collision: Collision::ParticleVsParticle {
-                        p1: l,
-                        p2: r,
-                        p1_cc: left.collisions_count,
-                        p2_cc: right.collisions_count,
-                    },
-                })
+        for &c in cells {
+            let cell = self.grid.cell(c);
+
+            for &r in &cell.particles {
+                let right = &self.image(&self.particles[r], &left);
+
+                let da = self.acceleration(&left) - self.acceleration(right);
+
+                if let Some(hit_time) = pvp::time_to_hit_accelerated(&left, right, da) {
+                    self.events.push(CollisionEvent {
+                        t: self.t + hit_time,
+                        collision: Collision::ParticleVsParticle {
+                            p1: left_id,
+                            p2: self.particles.id(r),
+                            p1_cc: left.collisions_count,
+                            p2_cc: right.collisions_count,
+                        },
+                    })
+                }
             }
+            segments.extend_from_slice(&cell.segments);
         }
 
-        for (s, segment) in self.segments.iter().enumerate() {
-            if let Some(t) = pvs::time_to_hit(&left, &segment) {
+        // Long segments may be registered in several cells at once.
+        segments.sort_unstable();
+        segments.dedup();
+
+        self.calculate_segments_events(l, segments);
+    }
+
+    fn calculate_segments_events(&mut self, l: usize, segments: Vec<usize>) {
+        let left = self.particles[l];
+        let left_id = self.particles.id(l);
+        let a = self.acceleration(&left);
+
+        // Segments can't move fixed particles
+        if left.fixed {
+            return;
+        }
+
+        for s in segments {
+            if let Some(t) = pvs::time_to_hit_accelerated(&left, &self.segments[s], a) {
                 self.events.push(CollisionEvent {
                     t: self.t + t,
                     collision: Collision::ParticleVsSegment {
-                        p: l,
-                        s: s,
+                        p: left_id,
+                        s,
                         p_cc: left.collisions_count,
                     },
                 })
@@ -180,12 +687,237 @@ impl Simulation {
         }
     }
 
-    // Moves all particles in the system using their current velocities.
+    fn calculate_cell_crossing_event(&mut self, l: usize) {
+        let particle = &self.particles[l];
+        let a = self.acceleration(particle);
+
+        let from = self.grid.particle_cell(l);
+
+        if let Some((t, cell)) = self.grid.time_to_leave(particle, from, a) {
+            let (p, p_cc) = (self.particles.id(l), particle.collisions_count);
+            let collision = if self.grid.wrap_shift(from, cell).is_zero() {
+                Collision::CellCrossing { p, cell, p_cc }
+            } else {
+                Collision::BoundaryCrossing { p, cell, p_cc }
+            };
+
+            self.events.push(CollisionEvent {
+                t: self.t + t,
+                collision,
+            })
+        }
+    }
+
+    // The particle, that has crossed the gate at the moment `crossed_at`,
+    // is looked for the next crossing since that moment.
+    fn calculate_gate_event(&mut self, l: usize, gate: usize, crossed_at: Option<f64>) {
+        let mut particle = self.particles[l];
+        if particle.fixed {
+            return;
+        }
+
+        let a = self.acceleration(&particle);
+        let since = crossed_at.unwrap_or(self.t);
+        particle.mv_accelerated(since - self.t, a);
+        if let Some(t) = self.gates[gate].time_to_cross(&particle, a, crossed_at.is_some()) {
+            self.events.push(CollisionEvent {
+                t: since + t,
+                collision: Collision::GateCrossing {
+                    p: self.particles.id(l),
+                    gate,
+                    p_cc: particle.collisions_count,
+                },
+            })
+        }
+    }
+
+    // Moves particle to the next cell. Only the cells that
+    // haven't been neighbours before are checked for collisions,
+    // events with the old neighbours stay valid.
+    fn cross_cell(&mut self, l: usize, cell: usize) {
+        let from = self.grid.particle_cell(l);
+        self.grid.move_particle(l, cell);
+
+        let entered: Vec<usize> = self
+            .grid
+            .neighbours(cell)
+            .filter(|&c| !self.grid.are_neighbours(from, c))
+            .collect();
+
+        self.calculate_cells_events(l, &entered);
+        self.calculate_cell_crossing_event(l);
+    }
+
+    // Moves particle to the opposite side of the domain. Segments are
+    // not periodic, so the collisions are predicted from scratch.
+    fn cross_boundary(&mut self, l: usize, cell: usize) {
+        let shift = self.grid.wrap_shift(self.grid.particle_cell(l), cell);
+        let old = self.particles[l];
+        let new = Particle {
+            pos: old.pos + shift,
+            ..old
+        };
+        self.update_moments(Some(&old), Some(&new));
+        if let Some(msd) = &mut self.msd {
+            msd.wrapped(self.particles.id(l), shift);
+        }
+        self.particles[l] = new;
+        self.grid.move_particle(l, cell);
+        self.calculate_particle_events(l);
+    }
+
+    // Copy of the particle `p`, that is shifted over the periodic
+    // borders to be the closest to the particle `to`.
+    fn image(&self, p: &Particle, to: &Particle) -> Particle {
+        let d = p.pos - to.pos;
+        let mut image = *p;
+        if self.periodic_x {
+            image.pos.x -= self.w * (d.x / self.w).round();
+        }
+        if self.periodic_y {
+            image.pos.y -= self.h * (d.y / self.h).round();
+        }
+        image
+    }
+
+    // Wraps the point into the domain along periodic axes.
+    fn wrap(&self, mut pos: Vec2) -> Vec2 {
+        if self.periodic_x {
+            pos.x = pos.x.rem_euclid(self.w);
+        }
+        if self.periodic_y {
+            pos.y = pos.y.rem_euclid(self.h);
+        }
+        pos
+    }
+
+    // Records the trajectory at every sampling moment up to `t`.
+    // There are no events in between, so particles are extrapolated
+    // to these moments. The system itself isn't moved, otherwise
+    // recording would change the rounding of the simulation.
+    fn record_until(&mut self, t: f64) {
+        while let Some(sample_t) = self.recorder.as_ref().and_then(|r| r.next_sample(t)) {
+            let particles = self
+                .particles_at(sample_t)
+                .map(|(id, p)| ParticleState::new(id, &p))
+                .collect();
+            self.recorder.as_mut().unwrap().record(Frame {
+                t: sample_t,
+                particles,
+            });
+        }
+        while let Some(sample_t) = self.msd.as_ref().and_then(|m| m.next_sample(t)) {
+            let particles: Vec<_> = self
+                .particles_at(sample_t)
+                .map(|(id, p)| (id, p.pos))
+                .collect();
+            self.msd
+                .as_mut()
+                .unwrap()
+                .sample(sample_t, particles.into_iter());
+        }
+        while let Some(sample_t) = self.rdf.as_ref().and_then(|r| r.next_sample(t)) {
+            let positions: Vec<Vec2> = self
+                .particles_at(sample_t)
+                .filter(|(_, p)| !p.fixed)
+                .map(|(_, p)| self.wrap(p.pos))
+                .collect();
+            self.rdf.as_mut().unwrap().sample(&positions);
+        }
+    }
+
+    // Particles extrapolated to the moment `t` without collisions.
+    fn particles_at(&self, t: f64) -> impl Iterator<Item = (ParticleId, Particle)> + '_ {
+        let dt = t - self.t;
+        self.particles.iter().map(move |(i, p)| {
+            let mut p = *p;
+            if !p.fixed {
+                p.mv_accelerated(dt, self.gravity);
+            }
+            (self.particles.id(i), p)
+        })
+    }
+
+    fn histogram<F: Fn(&Particle) -> f64>(
+        &self,
+        min: f64,
+        max: f64,
+        bins: usize,
+        value: F,
+    ) -> Histogram {
+        let mut histogram = Histogram::new(min, max, bins);
+        for (_, p) in self.particles.iter().filter(|(_, p)| !p.fixed) {
+            histogram.add(value(p));
+        }
+        histogram
+    }
+
+    fn maxwell(&self) -> Maxwell {
+        let t = self.stats().temperature;
+        Maxwell::new(self.particles.iter().map(|(_, p)| p), t)
+    }
+
+    fn update_moments(&mut self, old: Option<&Particle>, new: Option<&Particle>) {
+        self.moments.update(self.t, self.gravity, old, new);
+    }
+
+    fn log_event(&mut self, event: LoggedEvent) {
+        if let Some(log) = &mut self.event_log {
+            log.push(event);
+        }
+    }
+
+    // Domain borders along periodic axes are switched off.
+    #[inline]
+    fn is_segment_active(&self, s: usize) -> bool {
+        match s {
+            0 | 2 => !self.periodic_y,
+            1 | 3 => !self.periodic_x,
+            _ => true,
+        }
+    }
+
+    // Positions, where the particle is drawn. Particle that
+    // straddles the periodic border is drawn on both sides.
+    pub(crate) fn draw_positions(&self, p: &Particle) -> Vec<Vec2> {
+        let axis = |pos: f64, len: f64, periodic: bool| {
+            let mut coords = vec![pos];
+            if periodic && pos - p.r < 0. {
+                coords.push(pos + len);
+            }
+            if periodic && pos + p.r > len {
+                coords.push(pos - len);
+            }
+            coords
+        };
+
+        let xs = axis(p.pos.x, self.w, self.periodic_x);
+        let ys = axis(p.pos.y, self.h, self.periodic_y);
+        ys.iter()
+            .flat_map(|&y| xs.iter().map(move |&x| Vec2 { x, y }))
+            .collect()
+    }
+
+    // Moves all particles and segments in the system using their current velocities.
     #[inline]
     fn mv(&mut self, t: f64) {
         if self.t < t {
-            for particle in &mut self.particles {
-                particle.mv(t - self.t);
+            self.collisions_happend.clear();
+
+            let dt = t - self.t;
+            let moving = self.particles.iter_mut().filter(|(_, p)| !p.fixed);
+            if self.gravity.is_zero() {
+                for (_, particle) in moving {
+                    particle.mv(dt);
+                }
+            } else {
+                let gravity = self.gravity;
+                for (_, particle) in moving {
+                    particle.mv_accelerated(dt, gravity);
+                }
+            }
+            for segment in self.segments.iter_mut().filter(|s| s.is_moving()) {
+                *segment = segment.translated(segment.velocity * dt);
             }
             self.t = t;
         }
@@ -197,7 +929,7 @@ impl Simulation {
         if self
             .game_params
             .as_ref()
-            .map_or(false, |gp| gp.p_particle == i)
+            .is_some_and(|gp| gp.p_particle.index() == i)
         {
             self.game_params
                 .as_mut()
@@ -206,11 +938,19 @@ impl Simulation {
             return;
         }
 
+        let old = self.particles[i];
+        self.update_moments(Some(&old), Some(&new_particle));
         self.particles[i] = new_particle;
+        self.last_collisions[i] = self.t;
         self.calculate_particle_events(i);
     }
 
-    pub fn add_particle(&mut self, particle: &Particle) -> Option<usize> {
+    pub fn add_particle(&mut self, particle: &Particle) -> Option<ParticleId> {
+        let particle = &Particle {
+            pos: self.wrap(particle.pos),
+            ..*particle
+        };
+
         if self.is_collission(&particle) {
             log!(
                 "Warning: can't add particle {:?}, that collides with other particles.",
@@ -218,10 +958,77 @@ impl Simulation {
             );
             None
         } else {
-            self.particles.push(*particle);
-            self.initialized = false;
-            Some(self.particles.len() - 1)
+            let id = self.particles.insert(*particle);
+
+            if id.index() < self.last_collisions.len() {
+                self.last_collisions[id.index()] = f64::NEG_INFINITY;
+            }
+
+            // Running simulation is updated in place, unless
+            // the particle doesn't fit into the grid cells.
+            if self.initialized && 2. * particle.r <= self.grid.cell_size() {
+                self.last_collisions
+                    .resize(self.particles.slots(), f64::NEG_INFINITY);
+                self.grid.insert_particle(id.index(), particle);
+                self.calculate_particle_events(id.index());
+                self.update_moments(None, Some(particle));
+            } else {
+                self.initialized = false;
+            }
+            self.log_event(LoggedEvent::ParticleAdded {
+                t: self.t,
+                id,
+                particle: self.particles[id.index()],
+            });
+            Some(id)
+        }
+    }
+
+    // Removes particle from the simulation. Events predicted for
+    // this particle become stale and are skipped by the event loop.
+    pub fn remove_particle(&mut self, id: &ParticleId) -> Option<Particle> {
+        let removed = self.particles.remove(*id);
+
+        if removed.is_some() {
+            self.log_event(LoggedEvent::ParticleRemoved { t: self.t, id: *id });
+            if let Some(msd) = &mut self.msd {
+                msd.remove(*id);
+            }
+            if let Some(tracker) = &mut self.free_paths {
+                tracker.interrupt(*id);
+            }
+            if self.initialized {
+                self.grid.remove_particle(id.index());
+                self.update_moments(removed.as_ref(), None);
+            }
+            // Player's particle is gone, so is the game
+            if self
+                .game_params
+                .as_ref()
+                .is_some_and(|gp| gp.p_particle == *id)
+            {
+                self.game_params = None;
+            }
         }
+        removed
+    }
+
+    pub fn get_particle(&self, id: &ParticleId) -> Option<Particle> {
+        self.particles.get(*id).copied()
+    }
+
+    pub fn contains_particle(&self, id: &ParticleId) -> bool {
+        self.particles.contains(*id)
+    }
+
+    pub fn particles_count(&self) -> usize {
+        self.particles.len()
+    }
+
+    // Handles of all the living particles, in the same
+    // order as `get_particles` returns them.
+    pub fn get_particle_ids(&self) -> JsValue {
+        to_js_value(&self.particle_ids())
     }
 
     // Adds player's info and thus activates game mode.
@@ -231,14 +1038,17 @@ impl Simulation {
         player_uuid: &str,
         player_name: &str,
         game_end_cb: js_sys::Function,
-    ) -> Option<usize> {
+    ) -> Option<ParticleId> {
         let mut particle = particle.clone();
         particle.v = Vec2 { x: 0., y: 0. };
         let add_result = self.add_particle(&particle);
 
-        if let Some(index) = add_result {
+        if let Some(id) = add_result {
+            // Games are played in the default order of events,
+            // so the scores stay comparable
+            self.exact_events = false;
             self.game_params = Some(GameParams::new(
-                index,
+                id,
                 player_uuid.to_owned(),
                 player_name.to_owned(),
                 self.t,
@@ -248,223 +1058,2208 @@ impl Simulation {
         add_result
     }
 
-    // Checks wether any collision with `particle` is happening now.
-    fn is_collission(&self, particle: &Particle) -> bool {
-        for p in &self.particles {
-            if pvp::is_collision(&p, &particle) {
-                return true;
-            }
-        }
-        for s in &self.segments {
-            if pvs::is_collision(&particle, &s) {
-                return true;
-            }
+    // Activates game mode with the player's particle from the loaded scene.
+    pub fn start_game(
+        &mut self,
+        player_uuid: &str,
+        player_name: &str,
+        game_end_cb: js_sys::Function,
+    ) -> Option<ParticleId> {
+        if let Some(player) = self.scene_player {
+            self.add_player_particle(&player, player_uuid, player_name, game_end_cb)
+        } else {
+            log!("Warning! The scene has no game setup.");
+            None
         }
-        false
     }
 
-    pub fn mv_player_particle(&mut self, px: f64, py: f64) {
-        if let Some(g_params) = &self.game_params {
-            self.particles[g_params.p_particle].pos = Vec2 { x: px, y: py };
-            self.initialized = false;
-        } else {
-            log!("Warning! Game mode is inactive, add the player's particle first.")
-        }
+    // Builds the simulation from the scene object,
+    // throws an error message if the scene is invalid.
+    #[wasm_bindgen(js_name = from_scene)]
+    pub fn from_scene_js(scene: JsValue) -> Result<Simulation, JsValue> {
+        let scene: Scene = from_js_value(&scene)
+            .map_err(|e| JsValue::from_str(&SceneError::from(e).to_string()))?;
+        Simulation::from_scene(&scene).map_err(|e| JsValue::from_str(&e.to_string()))
     }
 
-    pub fn add_segment(&mut self, segment: &Segment) {
-        self.segments.push(*segment);
+    #[wasm_bindgen(js_name = to_scene)]
+    pub fn to_scene_js(&self) -> JsValue {
+        to_js_value(&self.to_scene())
+    }
+
+    // Compact binary snapshot of the whole simulation state.
+    pub fn save_checkpoint(&self) -> Vec<u8> {
+        self.checkpoint().to_bytes()
+    }
+
+    pub fn save_checkpoint_json(&self) -> String {
+        self.checkpoint().to_json()
+    }
+
+    // Returns the simulation to the saved state, see `restore`.
+    pub fn load_checkpoint(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        let checkpoint = Checkpoint::from_bytes(data).map_err(checkpoint_error)?;
+        self.restore(&checkpoint);
+        Ok(())
+    }
+
+    pub fn load_checkpoint_json(&mut self, json: &str) -> Result<(), JsValue> {
+        let checkpoint = Checkpoint::from_json(json).map_err(checkpoint_error)?;
+        self.restore(&checkpoint);
+        Ok(())
+    }
+
+    // Starts recording particles' states every `interval` of the simulation
+    // time, beginning from the current moment. Previous recording is dropped.
+    pub fn start_recording(&mut self, interval: f64) {
+        if !(interval > 0. && interval.is_finite()) {
+            log!(
+                "Warning! Recording interval must be positive, got {}.",
+                interval
+            );
+            return;
+        }
+        let header = TrajectoryHeader {
+            width: self.w,
+            height: self.h,
+            periodic_x: self.periodic_x,
+            periodic_y: self.periodic_y,
+        };
+        self.recorder = Some(TrajectoryRecorder::new(self.t, interval, header));
+    }
+
+    // Stops sampling, recorded frames are still available for export.
+    pub fn stop_recording(&mut self) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.active = false;
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.as_ref().is_some_and(|r| r.active)
+    }
+
+    pub fn clear_recording(&mut self) {
+        self.recorder = None;
+    }
+
+    pub fn get_trajectory_csv(&self) -> Option<String> {
+        self.trajectory().map(Trajectory::to_csv)
+    }
+
+    pub fn get_trajectory_xyz(&self) -> Option<String> {
+        self.trajectory().map(Trajectory::to_xyz)
+    }
+
+    // Compact binary trajectory, see `Trajectory::write_binary`.
+    pub fn get_trajectory_binary(&self) -> Option<Vec<u8>> {
+        self.trajectory().map(Trajectory::to_bytes)
+    }
+
+    // Starts tracking the displacement of the moving particles from
+    // the current moment. Mean squared displacement is sampled every
+    // `interval` of the simulation time. Previous tracking is dropped.
+    pub fn start_msd(&mut self, interval: f64) {
+        if !(interval > 0. && interval.is_finite()) {
+            log!("Warning! MSD interval must be positive, got {}.", interval);
+            return;
+        }
+        let particles = self
+            .particles
+            .iter()
+            .map(|(i, p)| (self.particles.id(i), p));
+        self.msd = Some(MsdTracker::new(self.t, interval, particles));
+    }
+
+    // Stops sampling, the curve is still available.
+    pub fn stop_msd(&mut self) {
+        if let Some(msd) = &mut self.msd {
+            msd.active = false;
+        }
+    }
+
+    pub fn is_msd_tracking(&self) -> bool {
+        self.msd.as_ref().is_some_and(|m| m.active)
+    }
+
+    pub fn clear_msd(&mut self) {
+        self.msd = None;
+    }
+
+    // Mean squared displacement at the current moment.
+    pub fn get_msd(&self) -> Option<f64> {
+        let particles = self
+            .particles
+            .iter()
+            .map(|(i, p)| (self.particles.id(i), p.pos));
+        self.msd.as_ref().map(|m| m.measure(particles).0)
+    }
+
+    // Sampled curve, see `MsdCurve`.
+    pub fn get_msd_curve(&self) -> JsValue {
+        to_js_value(&self.msd_curve())
+    }
+
+    // Diffusion coefficient estimated from the samples not earlier
+    // than `from` after the start, see `diffusion_coefficient`.
+    pub fn get_diffusion_coefficient(&self, from: f64) -> Option<f64> {
+        self.msd_curve()
+            .and_then(|curve| curve.diffusion_coefficient(from))
+    }
+
+    // Same for the particles of the colour `color` only.
+    pub fn get_species_diffusion_coefficient(&self, color: Option<RGBA>, from: f64) -> Option<f64> {
+        self.msd_curve()
+            .and_then(|curve| curve.species_diffusion_coefficient(color, from))
+    }
+
+    // Starts measuring free flights between collisions of particles, their
+    // distributions are collected over [0, max_path) and [0, max_time).
+    // Previous measurements are dropped.
+    pub fn start_free_paths(&mut self, max_path: f64, max_time: f64, bins: usize) {
+        self.free_paths = Some(FreePathTracker::new(max_path, max_time, bins));
+    }
+
+    pub fn clear_free_paths(&mut self) {
+        self.free_paths = None;
+    }
+
+    pub fn get_free_paths(&self) -> Option<FreePaths> {
+        self.free_paths().cloned()
+    }
+
+    pub fn get_mean_free_path(&self) -> Option<f64> {
+        self.free_paths().and_then(FreePaths::get_mean_free_path)
+    }
+
+    pub fn get_mean_free_time(&self) -> Option<f64> {
+        self.free_paths().and_then(FreePaths::get_mean_free_time)
+    }
+
+    // Theoretical free flight of the moving particles at the current
+    // packing fraction and temperature. Theory is made for identical
+    // disks, so the mean diameter and the mean mass are used.
+    pub fn get_enskog(&self) -> Enskog {
+        let moving: Vec<&Particle> = self
+            .particles
+            .iter()
+            .map(|(_, p)| p)
+            .filter(|p| !p.fixed)
+            .collect();
+        if moving.is_empty() {
+            return Enskog::default();
+        }
+        let n = moving.len();
+        let d = moving.iter().map(|p| 2. * p.r).sum::<f64>() / n as f64;
+        let m = moving.iter().map(|p| p.m).sum::<f64>() / n as f64;
+        Enskog::new(n, d, m, self.stats().temperature, self.w * self.h)
+    }
+
+    // Starts accumulating the radial distribution function of the moving
+    // particles every `interval` of the simulation time, beginning from
+    // the current moment. Previous data is dropped.
+    pub fn start_rdf(&mut self, r_max: f64, bins: usize, interval: f64) {
+        if !(interval > 0. && interval.is_finite()) {
+            log!("Warning! RDF interval must be positive, got {}.", interval);
+            return;
+        }
+        let rdf = RadialDistribution::new(
+            self.w,
+            self.h,
+            self.periodic_x,
+            self.periodic_y,
+            r_max,
+            bins,
+        );
+        self.rdf = Some(RdfSampler::new(self.t, interval, rdf));
+    }
+
+    // Stops sampling, the accumulated data is still available.
+    pub fn stop_rdf(&mut self) {
+        if let Some(rdf) = &mut self.rdf {
+            rdf.active = false;
+        }
+    }
+
+    pub fn clear_rdf(&mut self) {
+        self.rdf = None;
+    }
+
+    pub fn get_rdf(&self) -> Option<RadialDistribution> {
+        self.rdf().cloned()
+    }
+
+    // Starts logging every processed collision and every change made
+    // from outside, see `Replayer`. Previous log is dropped.
+    pub fn start_event_log(&mut self) {
+        let mut scene = self.to_scene();
+        // Player's particle is kept apart in the scene
+        scene.game = None;
+        let player = self.game_params.as_ref().map(|gp| gp.p_particle);
+        let ids = self
+            .particle_ids()
+            .into_iter()
+            .filter(|id| Some(*id) != player)
+            .collect();
+
+        let mut log = EventLog::new(self.t, scene, ids);
+        if let Some(id) = player {
+            log.push(LoggedEvent::ParticleAdded {
+                t: self.t,
+                id,
+                particle: self.particles[id.index()],
+            });
+        }
+        self.event_log = Some(log);
+    }
+
+    pub fn stop_event_log(&mut self) {
+        self.event_log = None;
+    }
+
+    pub fn get_event_log_json(&self) -> Option<String> {
+        self.event_log.as_ref().map(EventLog::to_json)
+    }
+
+    pub fn get_event_log_binary(&self) -> Option<Vec<u8>> {
+        self.event_log.as_ref().map(EventLog::to_bytes)
+    }
+
+    // Checks wether any collision with `particle` is happening now.
+    fn is_collission(&self, particle: &Particle) -> bool {
+        for (_, p) in self.particles.iter() {
+            if pvp::is_collision(&self.image(p, particle), &particle) {
+                return true;
+            }
+        }
+        for (i, s) in self.segments.iter().enumerate() {
+            if self.is_segment_active(i) && pvs::is_collision(&particle, &s) {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn mv_player_particle(&mut self, px: f64, py: f64) {
+        if let Some(g_params) = &self.game_params {
+            let id = g_params.p_particle;
+            self.particles[id.index()].pos = Vec2 { x: px, y: py };
+            self.initialized = false;
+            if let Some(tracker) = &mut self.free_paths {
+                tracker.interrupt(id);
+            }
+            self.log_event(LoggedEvent::ParticleMoved {
+                t: self.t,
+                id,
+                pos: Vec2 { x: px, y: py },
+            });
+        } else {
+            log!("Warning! Game mode is inactive, add the player's particle first.")
+        }
+    }
+
+    // Adds segment and returns its index.
+    pub fn add_segment(&mut self, segment: &Segment) -> usize {
+        self.segments.push(*segment);
         self.initialized = false;
+        self.log_event(LoggedEvent::SegmentAdded {
+            t: self.t,
+            segment: *segment,
+        });
+        self.segments.len() - 1
+    }
+
+    pub fn get_segment(&self, s: usize) -> Option<Segment> {
+        self.segments.get(s).copied()
+    }
+
+    // Changes velocity of the segment, zero velocity stops it.
+    pub fn set_segment_velocity(&mut self, s: usize, vx: f64, vy: f64) {
+        if let Some(segment) = self.segments.get_mut(s) {
+            *segment = segment.with_velocity(vx, vy);
+            self.initialized = false;
+            self.log_event(LoggedEvent::SegmentVelocityChanged {
+                t: self.t,
+                s,
+                velocity: Vec2 { x: vx, y: vy },
+            });
+        } else {
+            log!("Warning! There is no segment {}.", s);
+        }
+    }
+
+    // Makes the segment `s` a thermal wall with the temperature,
+    // or a usual reflecting one, if the temperature is not set.
+    pub fn set_segment_temperature(&mut self, s: usize, temperature: Option<f64>) {
+        if let Some(t) = temperature {
+            if !(t > 0. && t.is_finite()) {
+                log!("Warning! Temperature must be positive, got {}.", t);
+                return;
+            }
+        }
+        if let Some(segment) = self.segments.get_mut(s) {
+            segment.temperature = temperature;
+        } else {
+            log!("Warning! There is no segment {}.", s);
+        }
+    }
+
+    // Seed of the random numbers, that thermal walls use.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.random = Random::new(seed);
+    }
+
+    // Total work done on particles by moving segments.
+    // It's positive when the gas is being compressed.
+    pub fn get_wall_work(&self) -> f64 {
+        self.wall_work
+    }
+
+    pub fn get_pressure_window(&self) -> f64 {
+        self.walls.window()
+    }
+
+    // Pressure is averaged over the last `window` time units.
+    pub fn set_pressure_window(&mut self, window: f64) {
+        if window > 0. {
+            self.walls.set_window(window);
+        } else {
+            log!("Warning! Pressure window must be positive.");
+        }
+    }
+
+    // Number of particles that have hit the segment `s`.
+    pub fn get_segment_hits(&self, s: usize) -> u64 {
+        self.walls.load(s).hits
+    }
+
+    // Total normal impulse delivered to the segment `s`.
+    pub fn get_segment_impulse(&self, s: usize) -> f64 {
+        self.walls.load(s).normal_impulse
+    }
+
+    // Total energy given by the segment `s` to particles, it's negative
+    // when particles are cooled down, e.g. by the cold thermal wall.
+    pub fn get_segment_heat(&self, s: usize) -> f64 {
+        self.walls.load(s).heat
+    }
+
+    // Mean heat given by the segment `s` per unit of time since the wall
+    // stats were reset. Heat flows from the hot wall to the cold one.
+    pub fn get_heat_flow(&self, s: usize) -> f64 {
+        self.walls.heat_flow(self.t, s)
+    }
+
+    // Mean normal force per unit length on the segment `s` over the window.
+    pub fn get_pressure(&self, s: usize) -> f64 {
+        self.get_segments_pressure(vec![s])
+    }
+
+    // Pressure on the group of segments, that is the impulse
+    // delivered to all of them divided by their total length.
+    pub fn get_segments_pressure(&self, segments: Vec<usize>) -> f64 {
+        let length: f64 = segments
+            .iter()
+            .filter_map(|&s| self.segments.get(s))
+            .map(|segment| (segment.p2 - segment.p1).len())
+            .sum();
+        self.walls.pressure(self.t, &segments, length)
+    }
+
+    // Pressure on the domain borders, that are not switched off.
+    pub fn get_domain_pressure(&self) -> f64 {
+        let borders = (0..4).filter(|&s| self.is_segment_active(s)).collect();
+        self.get_segments_pressure(borders)
+    }
+
+    // Forgets all the hits, the window starts from the current moment.
+    pub fn reset_wall_stats(&mut self) {
+        self.walls.reset(self.t);
+    }
+
+    // Moving particles inside the region at the current moment.
+    pub fn get_region_stats(&self, region: &Region) -> RegionStats {
+        RegionStats::new(region, self.particles.iter().map(|(_, p)| p))
+    }
+
+    // Adds the gate, that counts particles crossing the segment
+    // from now on, and returns its index. See `Gate`.
+    pub fn add_gate(&mut self, segment: &Segment) -> usize {
+        self.gates.push(Gate::new(*segment, self.t));
+        let gate = self.gates.len() - 1;
+        if self.initialized {
+            for l in 0..self.particles.slots() {
+                if self.particles.is_alive(l) {
+                    self.calculate_gate_event(l, gate, None);
+                }
+            }
+        }
+        gate
+    }
+
+    pub fn get_gate(&self, gate: usize) -> Option<Gate> {
+        self.gates.get(gate).copied()
+    }
+
+    // Net number of particles crossed the gate forward per unit of time.
+    pub fn get_gate_flux(&self, gate: usize) -> Option<f64> {
+        self.gates.get(gate).map(|g| g.flux(self.t))
+    }
+
+    // Gates start counting from the current moment.
+    pub fn reset_gates(&mut self) {
+        for gate in self.gates.iter_mut() {
+            gate.reset(self.t);
+        }
+    }
+
+    pub fn get_draw_params(&self) -> DrawParams {
+        self.draw_params
+    }
+
+    pub fn set_draw_params(&mut self, draw_params: DrawParams) {
+        self.draw_params = draw_params;
+    }
+
+    pub fn draw(&self, ctx: &CanvasRenderingContext2d) {
+        render::draw(self, &mut CanvasRenderer::new(ctx));
+    }
+
+    // Vector image of the current state.
+    pub fn to_svg(&self) -> String {
+        let mut svg = SvgRenderer::new(self.w, self.h);
+        self.render(&mut svg);
+        svg.to_svg()
+    }
+
+    // Vector image of `count` states taken every `dt`, older states are
+    // more transparent. The simulation itself stays at the current state.
+    pub fn to_svg_time_lapse(&self, dt: f64, count: usize) -> String {
+        time_lapse_svg(self, dt, count)
+    }
+
+    // Registers javascript callback, that is called
+    // with the report of every processed collision.
+    pub fn add_collision_callback(&mut self, cb: js_sys::Function) {
+        self.add_observer(Box::new(JsCollisionObserver::new(cb)));
+    }
+
+    pub fn clear_collision_observers(&mut self) {
+        self.observers.clear();
+    }
+
+    pub fn is_game_mode_enabled(&self) -> bool {
+        self.game_params.is_some()
+    }
+
+    pub fn get_current_tick(&self) -> f64 {
+        self.t as f64
+    }
+
+    pub fn get_current_score(&self) -> Option<u32> {
+        self.game_params
+            .as_ref()
+            .map_or(None, |gp| Some(gp.get_score(self.t)))
+    }
+
+    // Kinetic energy, temperature, momentum and angular momentum
+    // of the moving particles at the current moment.
+    pub fn stats(&self) -> Stats {
+        if self.initialized {
+            self.moments.at(self.t, self.gravity).stats()
+        } else {
+            Moments::new(self.t, self.particles.iter().map(|(_, p)| p)).stats()
+        }
+    }
+
+    // Histogram of the moving particles' speeds over [min, max).
+    pub fn speed_histogram(&self, min: f64, max: f64, bins: usize) -> Histogram {
+        self.histogram(min, max, bins, |p| p.v.len())
+    }
+
+    pub fn velocity_x_histogram(&self, min: f64, max: f64, bins: usize) -> Histogram {
+        self.histogram(min, max, bins, |p| p.v.x)
+    }
+
+    pub fn velocity_y_histogram(&self, min: f64, max: f64, bins: usize) -> Histogram {
+        self.histogram(min, max, bins, |p| p.v.y)
+    }
+
+    // Density of the 2D Maxwell–Boltzmann speed distribution at
+    // the current temperature, that is where the speed histogram
+    // comes to in equilibrium. Particles may have different masses.
+    pub fn maxwell_speed_curve(&self, speeds: Vec<f64>) -> Vec<f64> {
+        let maxwell = self.maxwell();
+        speeds.into_iter().map(|v| maxwell.speed_pdf(v)).collect()
+    }
+
+    // Same for either of the velocity components.
+    pub fn maxwell_velocity_curve(&self, velocities: Vec<f64>) -> Vec<f64> {
+        let maxwell = self.maxwell();
+        velocities
+            .into_iter()
+            .map(|v| maxwell.component_pdf(v))
+            .collect()
+    }
+
+    // Copies particles' state into the buffers behind typed arrays
+    // and returns their length, that is the number of particle slots.
+    // Call it every time before reading the arrays.
+    pub fn update_state_arrays(&mut self) -> usize {
+        self.state_arrays.update(&self.particles);
+        self.state_arrays.len()
+    }
+
+    // Typed arrays are views into wasm memory indexed by `ParticleId.index()`,
+    // empty slots are zeroed. Views become invalid when wasm memory grows,
+    // so they must not be kept across calls into the simulation.
+    pub fn get_x_array(&self) -> Float64Array {
+        unsafe { Float64Array::view(&self.state_arrays.x) }
+    }
+
+    pub fn get_y_array(&self) -> Float64Array {
+        unsafe { Float64Array::view(&self.state_arrays.y) }
+    }
+
+    pub fn get_vx_array(&self) -> Float64Array {
+        unsafe { Float64Array::view(&self.state_arrays.vx) }
+    }
+
+    pub fn get_vy_array(&self) -> Float64Array {
+        unsafe { Float64Array::view(&self.state_arrays.vy) }
+    }
+
+    pub fn get_r_array(&self) -> Float64Array {
+        unsafe { Float64Array::view(&self.state_arrays.r) }
+    }
+
+    pub fn get_m_array(&self) -> Float64Array {
+        unsafe { Float64Array::view(&self.state_arrays.m) }
+    }
+
+    // 1 for the living particle, 0 for the empty slot.
+    pub fn get_alive_array(&self) -> Uint8Array {
+        unsafe { Uint8Array::view(&self.state_arrays.alive) }
+    }
+
+    // This function has serious performance penalties.
+    // Use `update_state_arrays` and typed arrays instead.
+    pub fn get_particles(&self) -> JsValue {
+        let particles: Vec<&Particle> = self.particles.iter().map(|(_, p)| p).collect();
+        to_js_value(&particles)
+    }
+}
+
+impl Simulation {
+    pub fn from_scene(scene: &Scene) -> Result<Simulation, SceneError> {
+        scene.validate()?;
+
+        let mut sim = Simulation::new(
+            scene.width,
+            scene.height,
+            scene.ticks_per_sec,
+            Some(scene.draw_params),
+        );
+        sim.gravity = scene.gravity;
+        sim.restitution = scene.restitution;
+        sim.collapse_time = scene.collapse_time;
+        sim.exact_events = scene.exact_events;
+        sim.set_periodic(scene.periodic_x, scene.periodic_y);
+
+        for (border, &temperature) in scene.border_temperatures.iter().enumerate() {
+            sim.segments[border].temperature = temperature;
+        }
+        for segment in &scene.segments {
+            sim.add_segment(&segment.into());
+        }
+        for (index, particle) in scene.particles.iter().enumerate() {
+            let particle = Particle {
+                pos: sim.wrap(particle.pos),
+                ..*particle
+            };
+            if sim.is_collission(&particle) {
+                return Err(SceneError::ParticleCollides { index });
+            }
+            sim.add_particle(&particle);
+        }
+        sim.scene_player = scene.game.map(|game| game.player);
+
+        Ok(sim)
+    }
+
+    // Current state of the simulation as a scene. Player's particle
+    // is saved separately, since it's added when the game starts.
+    pub fn to_scene(&self) -> Scene {
+        let player = self.game_params.as_ref().map(|gp| gp.p_particle);
+        let particles = self
+            .particles
+            .iter()
+            .filter(|(i, _)| player.map(|id| id.index()) != Some(*i))
+            .map(|(_, p)| *p)
+            .collect();
+        let player = player
+            .and_then(|id| self.particles.get(id).copied())
+            .or(self.scene_player);
+
+        Scene {
+            version: SCENE_VERSION,
+            width: self.w,
+            height: self.h,
+            ticks_per_sec: self.ticks_per_sec,
+            draw_params: self.draw_params,
+            gravity: self.gravity,
+            restitution: self.restitution,
+            collapse_time: self.collapse_time,
+            exact_events: self.exact_events,
+            periodic_x: self.periodic_x,
+            periodic_y: self.periodic_y,
+            particles,
+            // Domain borders are built from the domain size
+            segments: self.segments[4..].iter().map(SceneSegment::from).collect(),
+            border_temperatures: [
+                self.segments[0].temperature,
+                self.segments[1].temperature,
+                self.segments[2].temperature,
+                self.segments[3].temperature,
+            ],
+            game: player.map(|player| GameSetup { player }),
+        }
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            version: CHECKPOINT_VERSION,
+            w: self.w,
+            h: self.h,
+            initialized: self.initialized,
+            segments: self.segments.clone(),
+            moving_segments: self.moving_segments.clone(),
+            particles: self.particles.clone(),
+            events: self.events.clone().into_vec(),
+            collisions_happend: self.collisions_happend.iter().copied().collect(),
+            grid: self.grid.clone(),
+            t: self.t,
+            ticks_per_sec: self.ticks_per_sec,
+            tick_time: self.tick_time,
+            gravity: self.gravity,
+            periodic_x: self.periodic_x,
+            periodic_y: self.periodic_y,
+            restitution: self.restitution,
+            collapse_time: self.collapse_time,
+            last_collisions: self
+                .last_collisions
+                .iter()
+                .map(|&t| if t.is_finite() { Some(t) } else { None })
+                .collect(),
+            exact_events: self.exact_events,
+            wall_work: self.wall_work,
+            walls: self.walls.clone(),
+            random: self.random,
+            game: self.game_params.as_ref().map(|gp| GameCheckpoint {
+                p_particle: gp.p_particle,
+                player_uuid: gp.player_uuid.clone(),
+                player_name: gp.player_name.clone(),
+                game_started_tick: gp.game_started_tick,
+                game_ended: gp.game_ended,
+            }),
+            scene_player: self.scene_player,
+            draw_params: self.draw_params,
+        }
+    }
+
+    // Replaces the whole state with the checkpoint, observers and the
+    // trajectory recorder are kept, as well as the measured free paths
+    // and the radial distribution function.
+    // Wall pressure and heat statistics are restored with the rest of
+    // the state. Running event log, displacement tracking and gate
+    // counters start anew from the restored state.
+    // Game mode is restored only if the game is active now, because
+    // the callback of the current game is reused.
+    pub fn restore(&mut self, checkpoint: &Checkpoint) {
+        let c = checkpoint.clone();
+
+        self.w = c.w;
+        self.h = c.h;
+        self.initialized = c.initialized;
+        self.segments = c.segments;
+        self.moving_segments = c.moving_segments;
+        self.particles = c.particles;
+        // Gate crossings are predicted anew below, the restored ones
+        // would be counted twice
+        self.events = c
+            .events
+            .into_iter()
+            .filter(|e| !matches!(e.collision, Collision::GateCrossing { .. }))
+            .collect();
+        self.collisions_happend = c.collisions_happend.into_iter().collect();
+        self.grid = c.grid;
+        self.t = c.t;
+        self.ticks_per_sec = c.ticks_per_sec;
+        self.tick_time = c.tick_time;
+        self.gravity = c.gravity;
+        self.periodic_x = c.periodic_x;
+        self.periodic_y = c.periodic_y;
+        self.restitution = c.restitution;
+        self.collapse_time = c.collapse_time;
+        self.last_collisions = c
+            .last_collisions
+            .into_iter()
+            .map(|t| t.unwrap_or(f64::NEG_INFINITY))
+            .collect();
+        self.exact_events = c.exact_events;
+        self.wall_work = c.wall_work;
+        self.walls = c.walls;
+        self.random = c.random;
+        self.scene_player = c.scene_player;
+        self.draw_params = c.draw_params;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.skip_to(self.t);
+        }
+        if let Some(tracker) = &mut self.free_paths {
+            tracker.interrupt_all();
+        }
+        if let Some(rdf) = &mut self.rdf {
+            rdf.skip_to(self.t);
+        }
+        self.moments = Moments::new(self.t, self.particles.iter().map(|(_, p)| p));
+        // Gates aren't checkpointed, crossings are predicted for the current ones
+        self.reset_gates();
+        if self.initialized && !self.gates.is_empty() {
+            for l in 0..self.particles.slots() {
+                if self.particles.is_alive(l) {
+                    for gate in 0..self.gates.len() {
+                        self.calculate_gate_event(l, gate, None);
+                    }
+                }
+            }
+        }
+
+        let game_end_cb = self.game_params.take().map(|gp| gp.game_end_cb);
+        if let (Some(game), Some(game_end_cb)) = (c.game, game_end_cb) {
+            self.game_params = Some(GameParams {
+                p_particle: game.p_particle,
+                player_uuid: game.player_uuid,
+                player_name: game.player_name,
+                game_end_cb,
+                game_started_tick: game.game_started_tick,
+                game_ended: game.game_ended,
+            });
+        }
+        if self.event_log.is_some() {
+            self.start_event_log();
+        }
+        if let Some(interval) = self.msd.as_ref().filter(|m| m.active).map(|m| m.interval()) {
+            self.start_msd(interval);
+        }
+    }
+
+    pub fn event_log(&self) -> Option<&EventLog> {
+        self.event_log.as_ref()
+    }
+
+    // Hands over the log, logging is stopped.
+    pub fn take_event_log(&mut self) -> Option<EventLog> {
+        self.event_log.take()
+    }
+
+    pub fn rdf(&self) -> Option<&RadialDistribution> {
+        self.rdf.as_ref().map(|sampler| &sampler.rdf)
+    }
+
+    pub fn free_paths(&self) -> Option<&FreePaths> {
+        self.free_paths.as_ref().map(|tracker| &tracker.stats)
+    }
+
+    pub fn msd_curve(&self) -> Option<&MsdCurve> {
+        self.msd.as_ref().map(|m| &m.curve)
+    }
+
+    pub fn trajectory(&self) -> Option<&Trajectory> {
+        self.recorder.as_ref().map(|r| &r.trajectory)
+    }
+
+    // Hands over the frames recorded so far, recording goes on
+    // with the empty trajectory. Long runs may be flushed this way.
+    pub fn take_trajectory(&mut self) -> Option<Trajectory> {
+        self.recorder.as_mut().map(|r| {
+            let empty = Trajectory::new(r.trajectory.header);
+            std::mem::replace(&mut r.trajectory, empty)
+        })
+    }
+
+    pub fn from_checkpoint(checkpoint: &Checkpoint) -> Simulation {
+        let mut sim = Simulation::new(checkpoint.w, checkpoint.h, checkpoint.ticks_per_sec, None);
+        sim.restore(checkpoint);
+        sim
+    }
+
+    pub fn width(&self) -> f64 {
+        self.w
+    }
+
+    pub fn height(&self) -> f64 {
+        self.h
+    }
+
+    pub fn state_arrays(&self) -> &StateArrays {
+        &self.state_arrays
+    }
+
+    // Momentum received by the segment `s` since the last reset.
+    pub fn segment_load(&self, s: usize) -> SegmentLoad {
+        self.walls.load(s)
+    }
+
+    pub fn particles(&self) -> impl Iterator<Item = (ParticleId, &Particle)> {
+        self.particles
+            .iter()
+            .map(move |(i, p)| (self.particles.id(i), p))
+    }
+
+    // Draws the simulation with any backend, see `render::draw`.
+    pub fn render<R: Renderer + ?Sized>(&self, renderer: &mut R) {
+        render::draw(self, renderer);
+    }
+
+    // Segments, that are drawn according to `draw_params`.
+    pub(crate) fn drawn_segments(&self) -> impl Iterator<Item = &Segment> {
+        self.segments
+            .iter()
+            .enumerate()
+            .skip(if self.draw_params.borders { 0 } else { 4 })
+            .filter(move |(i, _)| self.is_segment_active(*i))
+            .map(|(_, segment)| segment)
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn CollisionObserver>) {
+        self.observers.push(observer);
+    }
+
+    pub fn particle_ids(&self) -> Vec<ParticleId> {
+        self.particles
+            .iter()
+            .map(|(i, _)| self.particles.id(i))
+            .collect()
+    }
+}
+
+fn checkpoint_error(e: CheckpointError) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DrawParams {
+    pub borders: bool,
+}
+
+#[wasm_bindgen]
+impl DrawParams {
+    pub fn new(borders: bool) -> DrawParams {
+        DrawParams { borders }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_log::Replayer;
+    use crate::{compare_floats, compare_vec2};
+    use std::f64::consts::PI;
+
+    // Gas of `cols` x `rows` particles on the grid starting at (x0, 10),
+    // rows are 20 apart, velocities are scattered by the grid indexes.
+    fn add_grid_gas(
+        sim: &mut Simulation,
+        cols: usize,
+        rows: usize,
+        x0: f64,
+        dx: f64,
+        mass: fn(usize, usize) -> f64,
+        r: f64,
+    ) {
+        for i in 0..cols {
+            for j in 0..rows {
+                sim.add_particle(&Particle::new(
+                    x0 + dx * i as f64,
+                    10. + 20. * j as f64,
+                    (7 * i + 3 * j) as f64 % 11. * 4. - 20.,
+                    (5 * i + 2 * j) as f64 % 7. * 4. - 12.,
+                    mass(i, j),
+                    r,
+                    None,
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_simulation() {
+        let mut sim = Simulation::new(100.0, 100.0, 100, None);
+
+        let p1 = Particle::new(
+            //  x    y    vx   vy   m   r   color
+            20., 30., 30., 20., 1., 5., None,
+        );
+        let p2 = Particle::new(
+            //  x    y     vx    vy   m   r   color
+            80., 70., -30., -20., 2., 5., None,
+        );
+
+        sim.add_particle(&p1);
+        sim.add_particle(&p2);
+
+        let expected_collision_tick = 85;
+        for _ in 0..(expected_collision_tick + 1) {
+            sim.tick();
+        }
+
+        compare_vec2!(
+            sim.particles[0].pos,
+            Vec2 { x: 45.8, y: 47.2 },
+            "p1 position right before collision",
+        );
+
+        compare_vec2!(
+            sim.particles[1].pos,
+            Vec2 { x: 54.2, y: 52.8 },
+            "p2 position right before collision",
+        );
+
+        assert_eq!(p1.v, sim.particles[0].v, "p1 collided too early");
+        assert_eq!(p2.v, sim.particles[1].v, "p2 collided too early");
+
+        // Moment of truth
+        sim.tick();
+
+        // Positions
+        compare_vec2!(
+            sim.particles[0].pos,
+            Vec2 {
+                x: 45.3,
+                y: 46.866666666666
+            },
+            "p1 position right after collision",
+        );
+        compare_vec2!(
+            sim.particles[1].pos,
+            Vec2 {
+                x: 54.3,
+                y: 52.866666666666,
+            },
+            "p2 position right after collision",
+        );
+
+        let expected_p1v = Vec2 {
+            x: -50.0,
+            y: -33.33333333333,
+        };
+
+        // Velocity
+        compare_vec2!(
+            sim.particles[0].v,
+            expected_p1v,
+            "p1 velocity right after collision",
+        );
+
+        compare_vec2!(
+            sim.particles[1].v,
+            Vec2 {
+                x: 10.0,
+                y: 6.66666666666,
+            },
+            "p2 velocity right after collision",
+        );
+
+        // And there's must be a wall collision for the p1
+        let expected_wall_collision_tick = 79;
+        for _ in 0..(expected_wall_collision_tick + 1) {
+            sim.tick();
+        }
+
+        compare_vec2!(
+            sim.particles[0].pos,
+            Vec2 { x: 5.3, y: 20.2 },
+            "p1 position right before the wall collision",
+        );
+
+        compare_vec2!(
+            sim.particles[0].v,
+            expected_p1v,
+            "p1 velocity right before the wall collision",
+        );
+        // Wall collision tick
+        sim.tick();
+
+        compare_vec2!(
+            sim.particles[0].pos,
+            Vec2 {
+                x: 5.8,
+                y: 19.8666666666,
+            },
+            "p1 position right after the wall collision",
+        );
+        compare_vec2!(
+            sim.particles[0].v,
+            Vec2 {
+                x: 50.,
+                y: -33.3333333333,
+            },
+            "p1 velocity right after the wall collision",
+        );
+    }
+
+    #[test]
+    fn test_simulation_crowded() {
+        let (width, height) = (200., 150.);
+        let mut sim = Simulation::new(width, height, 60, None);
+
+        let mut k = 0.;
+        for i in 0..13 {
+            for j in 0..9 {
+                k += 1.;
+                let particle = Particle::new(
+                    10. + 14. * i as f64,
+                    10. + 14. * j as f64,
+                    (k * 7.3f64).sin() * 80.,
+                    (k * 3.1f64).cos() * 80.,
+                    1.,
+                    4. + (k % 3.),
+                    None,
+                );
+                assert!(sim.add_particle(&particle).is_some());
+            }
+        }
+
+        for _ in 0..1000 {
+            sim.tick();
+        }
+
+        // Nobody escaped the domain
+        for (_, particle) in sim.particles.iter() {
+            assert!(
+                particle.pos.x > 0. && particle.pos.x < width,
+                "{:?}",
+                particle
+            );
+            assert!(
+                particle.pos.y > 0. && particle.pos.y < height,
+                "{:?}",
+                particle
+            );
+        }
+    }
+
+    #[test]
+    fn test_simulation_remove_particle() {
+        let mut sim = Simulation::new(100.0, 100.0, 100, None);
+
+        let p1 = Particle::new(20., 50., 30., 0., 1., 5., None);
+        let p2 = Particle::new(80., 50., -30., 0., 1., 5., None);
+
+        let id1 = sim.add_particle(&p1).unwrap();
+        let id2 = sim.add_particle(&p2).unwrap();
+
+        sim.tick();
+        assert_eq!(sim.remove_particle(&id2).map(|p| p.v), Some(p2.v));
+        assert_eq!(sim.remove_particle(&id2), None);
+        assert_eq!(sim.particles_count(), 1);
+
+        // Head-on collision would have happened at t = 0.83
+        for _ in 0..100 {
+            sim.tick();
+        }
+        let p1_now = sim.get_particle(&id1).unwrap();
+        assert_eq!(p1_now.v, p1.v);
+        assert_eq!(p1_now.collisions_count, 0);
+
+        // Slot is reused by the new particle, the old handle is stale
+        let id3 = sim.add_particle(&p2).unwrap();
+        assert_eq!(id3.index(), id2.index());
+        assert!(!sim.contains_particle(&id2));
+        assert_eq!(sim.particle_ids(), vec![id1, id3]);
+    }
+
+    #[test]
+    fn test_simulation_observer() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut sim = Simulation::new(100.0, 100.0, 100, None);
+        let id1 = sim
+            .add_particle(&Particle::new(20., 50., 30., 0., 1., 5., None))
+            .unwrap();
+        sim.add_particle(&Particle::new(80., 50., -30., 0., 2., 5., None));
+
+        let reports = Rc::new(RefCell::new(Vec::new()));
+        let sink = reports.clone();
+        sim.add_observer(Box::new(move |r: &CollisionReport| {
+            sink.borrow_mut().push(*r)
+        }));
+
+        for _ in 0..100 {
+            sim.tick();
+        }
+
+        let reports = reports.borrow();
+        assert!(!reports.is_empty());
+
+        match reports[0] {
+            CollisionReport::ParticleVsParticle {
+                t, p1, p2, impulse, ..
+            } => {
+                let (light, heavy) = if p1.id == id1 { (p1, p2) } else { (p2, p1) };
+
+                compare_floats!(t, 25. / 30.);
+                assert_eq!(light.v_before, Vec2 { x: 30., y: 0. });
+                assert_eq!(heavy.v_before, Vec2 { x: -30., y: 0. });
+                compare_vec2!(light.v_after, Vec2 { x: -50., y: 0. }, "light velocity");
+                compare_vec2!(heavy.v_after, Vec2 { x: 10., y: 0. }, "heavy velocity");
+                // Impulse belongs to `p1`
+                compare_vec2!(impulse, p1.dv() * sim.particles[p1.id.index()].m, "impulse");
+            }
+            _ => panic!("Particles must collide first, got {:?}", reports[0]),
+        }
+        for window in reports.windows(2) {
+            assert!(window[0].t() <= window[1].t());
+        }
+    }
+
+    #[test]
+    fn test_simulation_advance() {
+        let particles = [
+            Particle::new(20., 50., 30., 10., 1., 5., None),
+            Particle::new(80., 50., -30., 0., 2., 5., None),
+            Particle::new(50., 20., 0., 40., 1., 3., None),
+        ];
+
+        // Exact events don't depend on how the time is split
+        let mut ticked = Simulation::new(100.0, 100.0, 100, None);
+        let mut advanced = Simulation::new(100.0, 100.0, 100, None);
+        ticked.set_exact_events(true);
+        advanced.set_exact_events(true);
+        for particle in &particles {
+            ticked.add_particle(particle);
+            advanced.add_particle(particle);
+        }
+
+        for _ in 0..150 {
+            ticked.tick();
+        }
+        advanced.advance_by(0.5);
+        advanced.set_tick_time(0.25);
+        advanced.tick();
+        advanced.advance_to(1.5);
+
+        compare_floats!(ticked.get_current_tick(), advanced.get_current_tick());
+        for i in 0..particles.len() {
+            compare_vec2!(ticked.particles[i].pos, advanced.particles[i].pos, i);
+            compare_vec2!(ticked.particles[i].v, advanced.particles[i].v, i);
+        }
+    }
+
+    #[test]
+    fn test_simulation_exact_events() {
+        // Same collision as in `test_simulation`, but the particles
+        // collide at t = 0.861324947... instead of the tick's start
+        let mut sim = Simulation::new(100.0, 100.0, 100, None);
+        assert!(!sim.get_exact_events());
+        sim.set_exact_events(true);
+        sim.add_particle(&Particle::new(20., 30., 30., 20., 1., 5., None));
+        sim.add_particle(&Particle::new(80., 70., -30., -20., 2., 5., None));
+        for _ in 0..87 {
+            sim.tick();
+        }
+
+        compare_vec2!(
+            sim.particles[0].pos,
+            Vec2 {
+                x: 45.405996075495,
+                y: 46.937330716997,
+            },
+            "p1 position right after collision",
+        );
+        compare_vec2!(
+            sim.particles[1].pos,
+            Vec2 {
+                x: 54.247001962252,
+                y: 52.831334641501,
+            },
+            "p2 position right after collision",
+        );
+
+        assert!(sim.to_scene().exact_events);
+        assert!(Simulation::from_checkpoint(&sim.checkpoint()).get_exact_events());
+    }
+
+    #[test]
+    fn test_simulation_step_event() {
+        let mut sim = Simulation::new(100.0, 100.0, 100, None);
+        sim.add_particle(&Particle::new(20., 50., 30., 0., 1., 5., None));
+        sim.add_particle(&Particle::new(80., 50., -30., 0., 1., 5., None));
+
+        // Head-on collision
+        compare_floats!(sim.step_event().unwrap(), 25. / 30.);
+        compare_floats!(sim.get_current_tick(), 25. / 30.);
+        assert_eq!(sim.particles[0].v, Vec2 { x: -30., y: 0. });
+        assert_eq!(sim.particles[1].v, Vec2 { x: 30., y: 0. });
+
+        // Both particles hit the walls at the same time
+        let t = sim.step_event().unwrap();
+        compare_floats!(sim.step_event().unwrap(), t);
+        assert_eq!(sim.particles[0].v, Vec2 { x: 30., y: 0. });
+        assert_eq!(sim.particles[1].v, Vec2 { x: -30., y: 0. });
+
+        let mut still = Simulation::new(100.0, 100.0, 100, None);
+        still.add_particle(&Particle::new(20., 50., 0., 0., 1., 5., None));
+        assert_eq!(still.step_event(), None);
+    }
+
+    #[test]
+    fn test_simulation_restitution() {
+        let mut sim = Simulation::new(100.0, 100.0, 100, None);
+        sim.add_segment(&Segment::new(50., 10., 50., 90.).with_restitution(0.5));
+
+        let ball = Particle::new(20., 50., 30., 0., 1., 5., None);
+        let id = sim.add_particle(&ball.with_restitution(0.8)).unwrap();
+
+        // Bounces off the segment: min(0.8, 0.5)
+        sim.advance_to(1.);
+        assert_eq!(sim.get_particle(&id).unwrap().v, Vec2 { x: -15., y: 0. });
+
+        // Bounces off the border: min(0.8, default)
+        sim.set_restitution(0.9);
+        sim.advance_to(4.);
+        compare_vec2!(
+            sim.get_particle(&id).unwrap().v,
+            Vec2 { x: 12., y: 0. },
+            "v"
+        );
+    }
+
+    #[test]
+    fn test_simulation_inelastic_collapse() {
+        let mut sim = Simulation::new(100.0, 100.0, 100, None);
+        sim.set_restitution(0.);
+
+        for i in 0..6 {
+            for j in 0..6 {
+                let (x, y) = (10. + 15. * i as f64, 10. + 15. * j as f64);
+                sim.add_particle(&Particle::new(x, y, 50. - y, x - 50., 1., 5., None));
+            }
+        }
+
+        // Must not hang, even though particles lose all the energy
+        for _ in 0..300 {
+            sim.tick();
+        }
+
+        let energy: f64 = sim.particles.iter().map(|(_, p)| p.v.len_sqr()).sum();
+        assert!(energy < 36. * 50. * 50.);
+    }
+
+    #[test]
+    fn test_simulation_gravity() {
+        let (width, height, g) = (100., 100., 200.);
+        let mut sim = Simulation::new(width, height, 60, None);
+        sim.set_gravity(0., g);
+
+        let balls = [
+            Particle::new(20., 20., 15., 0., 1., 5., None),
+            Particle::new(50., 60., -40., -30., 2., 4., None),
+            Particle::new(80., 40., 0., 10., 1., 6., None),
+        ];
+        for ball in &balls {
+            sim.add_particle(ball);
+        }
+
+        let energy = |sim: &Simulation| -> f64 {
+            sim.particles
+                .iter()
+                .map(|(_, p)| p.m * (0.5 * p.v.len_sqr() + g * (height - p.pos.y)))
+                .sum()
+        };
+        let initial_energy = energy(&sim);
+
+        for _ in 0..600 {
+            sim.tick();
+            for (_, p) in sim.particles.iter() {
+                assert!(p.pos.y + p.r < height + 1e-6, "{:?}", p);
+            }
+        }
+
+        assert!((energy(&sim) - initial_energy).abs() < 1e-6 * initial_energy);
+    }
+
+    #[test]
+    fn test_simulation_gravity_inelastic() {
+        let (width, height) = (100., 100.);
+        let mut sim = Simulation::new(width, height, 60, None);
+        sim.set_exact_events(true);
+        sim.set_gravity(0., 300.);
+        sim.set_restitution(0.5);
+        sim.set_collapse_time(1e-3);
+
+        for i in 0..5 {
+            sim.add_particle(&Particle::new(
+                15. + 17. * i as f64,
+                20.,
+                30.,
+                0.,
+                1.,
+                5.,
+                None,
+            ));
+        }
+
+        // Everybody lies on the floor after a while
+        for _ in 0..600 {
+            sim.tick();
+        }
+        for (_, p) in sim.particles.iter() {
+            assert!((p.pos.y - (height - p.r)).abs() < 0.1, "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn test_simulation_fixed_particles() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let mut sim = Simulation::new(100., 100., 60, None);
+        sim.set_gravity(0., 100.);
+
+        let mut pegs = Vec::new();
+        for i in 0..4 {
+            let peg = Particle::new(20. + 20. * i as f64, 60., 0., 0., 1., 4., None).as_fixed();
+            pegs.push((sim.add_particle(&peg).unwrap(), peg));
+        }
+        for i in 0..6 {
+            sim.add_particle(&Particle::new(
+                12. + 15. * i as f64,
+                10.,
+                0.,
+                0.,
+                1.,
+                3.,
+                None,
+            ));
+        }
+
+        let hits = Rc::new(Cell::new(0));
+        let counter = hits.clone();
+        sim.add_observer(Box::new(move |report: &CollisionReport| {
+            if let CollisionReport::ParticleVsParticle { .. } = report {
+                counter.set(counter.get() + 1);
+            }
+        }));
+
+        for _ in 0..300 {
+            sim.tick();
+        }
+
+        for (id, peg) in &pegs {
+            assert_eq!(sim.get_particle(id).unwrap(), *peg);
+        }
+        for (_, p) in sim.particles.iter().filter(|(_, p)| !p.fixed) {
+            for (_, peg) in &pegs {
+                assert!((p.pos - peg.pos).len() > p.r + peg.r - 1e-6);
+            }
+        }
+        assert!(hits.get() > 0);
     }
 
-    pub fn draw(&self, ctx: &CanvasRenderingContext2d) {
-        ctx.clear_rect(0.0, 0.0, self.w, self.h);
-
-        for particle in &self.particles {
-            ctx.begin_path();
-            ctx.arc(
-                particle.pos.x,
-                particle.pos.y,
-                particle.r,
-                0.0,
-                2.0 * std::f64::consts::PI,
-            )
-            .unwrap();
-            ctx.close_path();
-            if let Some(color) = particle.color {
-                ctx.set_fill_style(&JsValue::from_str(&color.as_css_hex()));
-                ctx.fill();
-            };
-            ctx.stroke();
+    #[test]
+    fn test_simulation_piston() {
+        let mut sim = Simulation::new(100., 100., 60, None);
+        let piston = sim.add_segment(&Segment::new(90., 0., 90., 100.).with_velocity(-20., 0.));
+
+        for i in 0..5 {
+            for j in 0..5 {
+                sim.add_particle(&Particle::new(
+                    10. + 15. * i as f64,
+                    10. + 18. * j as f64,
+                    (7 * i + 3 * j) as f64 % 11. - 5.,
+                    (5 * i + 2 * j) as f64 % 7. - 3.,
+                    1.,
+                    2.,
+                    None,
+                ));
+            }
         }
 
-        let segments_to_draw = if self.draw_params.borders {
-            &self.segments[..]
-        } else {
-            &self.segments[4..]
+        let energy = |sim: &Simulation| -> f64 {
+            sim.particles
+                .iter()
+                .map(|(_, p)| 0.5 * p.m * p.v.len_sqr())
+                .sum()
         };
+        let initial_energy = energy(&sim);
 
-        for segment in segments_to_draw {
-            ctx.begin_path();
-            ctx.move_to(segment.p1.x, segment.p1.y);
-            ctx.line_to(segment.p2.x, segment.p2.y);
-            ctx.close_path();
-            ctx.stroke();
+        for _ in 0..60 {
+            sim.tick();
         }
-    }
 
-    pub fn is_game_mode_enabled(&self) -> bool {
-        self.game_params.is_some()
-    }
+        let x = sim.get_segment(piston).unwrap().p1.x;
+        compare_floats!(x, 70.);
+        for (_, p) in sim.particles.iter() {
+            assert!(p.pos.x + p.r < x + 1e-6, "{:?}", p);
+        }
 
-    pub fn get_current_tick(&self) -> f64 {
-        self.t as f64
-    }
+        // Compressed gas gets hotter exactly by the work done on it
+        assert!(sim.get_wall_work() > 0.);
+        assert!((energy(&sim) - initial_energy - sim.get_wall_work()).abs() < 1e-6);
 
-    pub fn get_current_score(&self) -> Option<u32> {
-        self.game_params
-            .as_ref()
-            .map_or(None, |gp| Some(gp.get_score(self.t)))
+        // Stopped piston doesn't do any work
+        sim.set_segment_velocity(piston, 0., 0.);
+        let work = sim.get_wall_work();
+        for _ in 0..60 {
+            sim.tick();
+        }
+        assert_eq!(sim.get_wall_work(), work);
     }
 
-    // This function has serious performance penalties.
-    // Use it with caution.
-    pub fn get_particles(&self) -> JsValue {
-        JsValue::from_serde(&self.particles.clone()).unwrap()
-    }
-}
+    #[test]
+    fn test_simulation_periodic() {
+        let mut sim = Simulation::new(100., 100., 10, None);
+        sim.set_periodic(true, false);
 
-#[wasm_bindgen]
-#[derive(Debug, Default, Serialize, Deserialize)]
-pub struct DrawParams {
-    pub borders: bool,
-}
+        let id = sim
+            .add_particle(&Particle::new(90., 20., 30., 0., 1., 2., None))
+            .unwrap();
+        // Added particles are wrapped into the domain
+        let wrapped = sim
+            .add_particle(&Particle::new(-30., 80., 0., 0., 1., 2., None))
+            .unwrap();
+        compare_floats!(sim.get_particle(&wrapped).unwrap().pos.x, 70.);
 
-#[wasm_bindgen]
-impl DrawParams {
-    pub fn new(borders: bool) -> DrawParams {
-        DrawParams { borders }
+        sim.advance_to(1.);
+        let p = sim.get_particle(&id).unwrap();
+        compare_floats!(p.pos.x, 20.);
+        compare_floats!(p.v.x, 30.);
+        assert_eq!(p.collisions_count, 0);
+
+        // Straddling particle is drawn on both sides
+        let p = Particle::new(99., 20., 0., 0., 1., 2., None);
+        let positions = sim.draw_positions(&p);
+        assert_eq!(positions.len(), 2);
+        compare_floats!(positions[0].x, 99.);
+        compare_floats!(positions[1].x, -1.);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::compare_vec2;
+    #[test]
+    fn test_simulation_periodic_collision() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut sim = Simulation::new(100., 100., 10, None);
+        sim.set_exact_events(true);
+        sim.set_periodic(true, true);
+
+        let left = sim
+            .add_particle(&Particle::new(5., 50., -10., 0., 1., 2., None))
+            .unwrap();
+        let right = sim
+            .add_particle(&Particle::new(95., 50., 10., 0., 1., 2., None))
+            .unwrap();
+
+        let reports = Rc::new(RefCell::new(Vec::new()));
+        let sink = reports.clone();
+        sim.add_observer(Box::new(move |r: &CollisionReport| {
+            sink.borrow_mut().push(*r)
+        }));
+
+        // They meet through the border
+        compare_floats!(sim.step_event().unwrap(), 0.3);
+        compare_floats!(sim.get_particle(&left).unwrap().v.x, 10.);
+        compare_floats!(sim.get_particle(&right).unwrap().v.x, -10.);
+
+        // And then again in the middle of the domain
+        compare_floats!(sim.step_event().unwrap(), 4.9);
+        compare_floats!(sim.get_particle(&left).unwrap().pos.x, 48.);
+
+        for r in reports.borrow().iter() {
+            assert!(matches!(r, CollisionReport::ParticleVsParticle { .. }));
+        }
+    }
 
     #[test]
-    fn test_simulation() {
-        let mut sim = Simulation::new(100.0, 100.0, 100, None);
+    fn test_simulation_periodic_gas() {
+        let mut sim = Simulation::new(100., 100., 60, None);
+        sim.set_periodic(true, true);
 
-        let p1 = Particle::new(
-            //  x    y    vx   vy   m   r   color
-            20., 30., 30., 20., 1., 5., None,
-        );
-        let p2 = Particle::new(
-            //  x    y     vx    vy   m   r   color
-            80., 70., -30., -20., 2., 5., None,
-        );
+        for i in 0..6 {
+            for j in 0..6 {
+                sim.add_particle(&Particle::new(
+                    8. + 16. * i as f64,
+                    8. + 16. * j as f64,
+                    (7 * i + 3 * j) as f64 % 11. * 5. - 25.,
+                    (5 * i + 2 * j) as f64 % 7. * 5. - 15.,
+                    1. + (i % 2) as f64,
+                    3.,
+                    None,
+                ));
+            }
+        }
 
-        sim.add_particle(&p1);
-        sim.add_particle(&p2);
+        let totals = |sim: &Simulation| -> (f64, Vec2) {
+            sim.particles
+                .iter()
+                .fold((0., Vec2 { x: 0., y: 0. }), |(e, m), (_, p)| {
+                    (e + 0.5 * p.m * p.v.len_sqr(), m + p.v * p.m)
+                })
+        };
+        let (energy, momentum) = totals(&sim);
 
-        let expected_collision_tick = 85;
-        for _ in 0..(expected_collision_tick + 1) {
+        for _ in 0..300 {
             sim.tick();
         }
 
-        compare_vec2!(
-            sim.particles[0].pos,
-            Vec2 { x: 45.8, y: 47.2 },
-            "p1 position right before collision",
+        let (new_energy, new_momentum) = totals(&sim);
+        assert!((new_energy - energy).abs() < 1e-6 * energy);
+        assert!((new_momentum - momentum).len() < 1e-6);
+
+        for (i, p) in sim.particles.iter() {
+            assert!(p.pos.x >= 0. && p.pos.x <= 100. && p.pos.y >= 0. && p.pos.y <= 100.);
+            for (j, other) in sim.particles.iter().filter(|(j, _)| *j != i) {
+                let image = sim.image(other, p);
+                assert!(
+                    (image.pos - p.pos).len() > p.r + other.r - 1e-6,
+                    "{} {}",
+                    i,
+                    j
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_simulation_scene() {
+        let mut scene = Scene::new(100., 100.);
+        scene.gravity = Vec2 { x: 0., y: 50. };
+        scene.restitution = 0.9;
+        scene.particles = vec![
+            Particle::new(20., 20., 15., 0., 1., 5., None),
+            Particle::new(60., 20., -15., 10., 2., 5., None).with_restitution(0.5),
+        ];
+        scene.segments = vec![SceneSegment::from(&Segment::new(10., 60., 90., 70.))];
+
+        let mut sim = Simulation::from_scene(&scene).unwrap();
+        assert_eq!(sim.to_scene(), scene);
+
+        sim.advance_to(2.);
+        let saved = sim.to_scene();
+        assert_eq!(
+            saved.particles,
+            sim.particles.iter().map(|(_, p)| *p).collect::<Vec<_>>()
         );
 
-        compare_vec2!(
-            sim.particles[1].pos,
-            Vec2 { x: 54.2, y: 52.8 },
-            "p2 position right before collision",
+        // Restored simulation goes on the same way, up to rounding errors
+        let mut restored = Simulation::from_scene(&saved).unwrap();
+        sim.advance_to(3.);
+        restored.advance_to(1.);
+        for ((_, p1), (_, p2)) in sim.particles.iter().zip(restored.particles.iter()) {
+            assert!((p1.pos - p2.pos).len() < 1e-6, "{:?} != {:?}", p1, p2);
+        }
+
+        scene
+            .particles
+            .push(Particle::new(24., 24., 0., 0., 1., 2., None));
+        assert_eq!(
+            Simulation::from_scene(&scene).err(),
+            Some(SceneError::ParticleCollides { index: 2 })
         );
+    }
 
-        assert_eq!(p1.v, sim.particles[0].v, "p1 collided too early");
-        assert_eq!(p2.v, sim.particles[1].v, "p2 collided too early");
+    #[test]
+    fn test_simulation_checkpoint() {
+        let mut sim = Simulation::new(100., 100., 60, None);
+        sim.set_gravity(0., 30.);
+        sim.set_restitution(0.95);
+        sim.add_segment(&Segment::new(95., 0., 95., 100.).with_velocity(-2., 0.));
+        sim.add_particle(&Particle::new(47., 40., 0., 0., 1., 4., None).as_fixed());
+        add_grid_gas(&mut sim, 5, 4, 10., 15., |_, _| 1., 3.);
+        let removed = sim.particle_ids()[3];
+        sim.remove_particle(&removed);
 
-        // Moment of truth
-        sim.tick();
+        for _ in 0..50 {
+            sim.tick();
+        }
+        let checkpoint = sim.checkpoint();
+        let binary =
+            Simulation::from_checkpoint(&Checkpoint::from_bytes(&checkpoint.to_bytes()).unwrap());
+        let json =
+            Simulation::from_checkpoint(&Checkpoint::from_json(&checkpoint.to_json()).unwrap());
 
-        // Positions
-        compare_vec2!(
-            sim.particles[0].pos,
-            Vec2 {
-                x: 45.3,
-                y: 46.866666666666
-            },
-            "p1 position right after collision",
+        for _ in 0..100 {
+            sim.tick();
+        }
+        let state =
+            |sim: &Simulation| -> Vec<Particle> { sim.particles.iter().map(|(_, p)| *p).collect() };
+
+        for mut restored in [binary, json] {
+            assert_eq!(restored.get_current_tick(), checkpoint.t());
+            for _ in 0..100 {
+                restored.tick();
+            }
+            assert_eq!(state(&restored), state(&sim));
+            assert_eq!(restored.particle_ids(), sim.particle_ids());
+            assert_eq!(restored.get_wall_work(), sim.get_wall_work());
+            // Wall statistics go on from the checkpoint
+            assert!(sim.get_segment_hits(4) > 0);
+            for s in 0..5 {
+                assert_eq!(restored.get_segment_hits(s), sim.get_segment_hits(s));
+                assert_eq!(restored.get_segment_impulse(s), sim.get_segment_impulse(s));
+                assert_eq!(restored.get_pressure(s), sim.get_pressure(s));
+                assert_eq!(restored.get_segment_heat(s), sim.get_segment_heat(s));
+            }
+        }
+
+        assert_eq!(
+            Checkpoint::from_bytes(&[1, 0]).unwrap_err(),
+            CheckpointError::Decode("data is too short".to_owned())
         );
-        compare_vec2!(
-            sim.particles[1].pos,
-            Vec2 {
-                x: 54.3,
-                y: 52.866666666666,
-            },
-            "p2 position right after collision",
+        let mut data = checkpoint.to_bytes();
+        data[0] = 42;
+        assert_eq!(
+            Checkpoint::from_bytes(&data).unwrap_err().to_string(),
+            "checkpoint version 42 is not supported, expected 4"
         );
+    }
 
-        let expected_p1v = Vec2 {
-            x: -50.0,
-            y: -33.33333333333,
+    #[test]
+    fn test_simulation_trajectory() {
+        let build = || {
+            let mut sim = Simulation::new(100., 100., 60, None);
+            sim.set_gravity(0., 20.);
+            sim.add_particle(&Particle::new(20., 50., 30., 10., 1., 5., None));
+            sim.add_particle(&Particle::new(80., 50., -30., 0., 2., 5., None));
+            sim.add_particle(&Particle::new(50., 20., 0., 40., 1., 3., None).as_fixed());
+            sim
         };
 
-        // Velocity
+        let mut recorded = build();
+        recorded.start_recording(0.1);
+        for _ in 0..63 {
+            recorded.tick();
+        }
+        let trajectory = recorded.trajectory().unwrap();
+        assert_eq!(trajectory.frames.len(), 11);
+        for (i, frame) in trajectory.frames.iter().enumerate() {
+            compare_floats!(frame.t, 0.1 * i as f64);
+            assert_eq!(frame.particles.len(), 3);
+        }
+
+        // Recording doesn't affect the simulation
+        let mut sim = build();
+        for _ in 0..63 {
+            sim.tick();
+        }
+        let state =
+            |sim: &Simulation| -> Vec<Particle> { sim.particles.iter().map(|(_, p)| *p).collect() };
+        assert_eq!(state(&recorded), state(&sim));
+
+        // Frames match the system at the moment of the sample
+        let mut sim = build();
+        sim.advance_to(0.5);
+        for (frame, (i, p)) in trajectory.frames[5]
+            .particles
+            .iter()
+            .zip(sim.particles.iter())
+        {
+            assert_eq!(frame.id, sim.particles.id(i));
+            compare_vec2!(frame.pos, p.pos, i);
+            compare_vec2!(frame.v, p.v, i);
+        }
+
+        let taken = recorded.take_trajectory().unwrap();
+        assert_eq!(taken.frames.len(), 11);
+        recorded.advance_by(0.2);
+        assert_eq!(recorded.trajectory().unwrap().frames.len(), 2);
+        recorded.stop_recording();
+        recorded.advance_by(0.5);
+        assert!(!recorded.is_recording());
+        assert_eq!(recorded.trajectory().unwrap().frames.len(), 2);
+    }
+
+    #[test]
+    fn test_simulation_event_log() {
+        let mut sim = Simulation::new(100., 100., 60, None);
+        sim.set_periodic(true, false);
+        sim.set_gravity(0., 20.);
+        sim.set_restitution(0.9);
+        let shelf = sim.add_segment(&Segment::new(30., 70., 60., 70.));
+        sim.add_particle(&Particle::new(50., 40., 0., 0., 1., 4., None).as_fixed());
+        add_grid_gas(&mut sim, 5, 3, 10., 18., |_, _| 1., 3.);
+        sim.advance_to(0.3);
+
+        sim.start_event_log();
+        sim.start_recording(0.1);
+        sim.advance_to(1.);
+        let removed = sim.particle_ids()[4];
+        sim.remove_particle(&removed);
+        sim.add_particle(&Particle::new(50., 90., 10., -30., 2., 4., None));
+        sim.set_segment_velocity(shelf, 0., -5.);
+        sim.advance_to(1.5);
+        sim.set_gravity(5., 10.);
+        sim.advance_to(2.5);
+
+        let log = sim.take_event_log().unwrap();
+        assert_eq!(EventLog::from_json(&log.to_json()).unwrap(), log);
+        assert_eq!(EventLog::from_bytes(&log.to_bytes()).unwrap(), log);
+
+        let mut replayer = Replayer::new(log);
+        let trajectory = sim.take_trajectory().unwrap();
+        assert_eq!(trajectory.frames.len(), 23);
+        // Going back and forth gives the same result
+        for frame in trajectory
+            .frames
+            .iter()
+            .chain(trajectory.frames.iter().rev())
+        {
+            replayer.seek(frame.t);
+            let particles = replayer.particles();
+            assert_eq!(particles.len(), frame.particles.len());
+            for ((id, p), state) in particles.iter().zip(&frame.particles) {
+                assert_eq!(*id, state.id);
+                // Particles may be on different sides of the periodic border
+                let dx = (p.pos.x - state.pos.x + 50.).rem_euclid(100.) - 50.;
+                assert!(dx.abs() < 1e-6, "{} {:?} {:?}", frame.t, p, state);
+                compare_floats!(p.pos.y, state.pos.y);
+                compare_vec2!(p.v, state.v, "velocity");
+            }
+        }
         compare_vec2!(
-            sim.particles[0].v,
-            expected_p1v,
-            "p1 velocity right after collision",
+            replayer.segments()[shelf].p1,
+            Vec2 { x: 30., y: 70. },
+            "shelf"
         );
-
+        replayer.seek(2.5);
         compare_vec2!(
-            sim.particles[1].v,
-            Vec2 {
-                x: 10.0,
-                y: 6.66666666666,
-            },
-            "p2 velocity right after collision",
+            replayer.segments()[shelf].p1,
+            sim.get_segment(shelf).unwrap().p1,
+            "shelf"
         );
+    }
 
-        // And there's must be a wall collision for the p1
-        let expected_wall_collision_tick = 79;
-        for _ in 0..(expected_wall_collision_tick + 1) {
+    #[test]
+    fn test_simulation_stats() {
+        let gas = |sim: &mut Simulation| {
+            add_grid_gas(sim, 6, 4, 8., 16., |i, j| 1. + (i + j) as f64 % 3., 3.);
+        };
+
+        // Elastic gas without walls conserves energy and momentum
+        let mut sim = Simulation::new(100., 100., 60, None);
+        sim.set_periodic(true, true);
+        gas(&mut sim);
+        let initial = sim.stats();
+        assert_eq!(initial.particles, 24);
+        for _ in 0..120 {
             sim.tick();
         }
+        let stats = sim.stats();
+        assert!((stats.kinetic_energy - initial.kinetic_energy).abs() < 1e-9);
+        compare_floats!(stats.temperature, stats.kinetic_energy / 24.);
+        assert!((stats.momentum - initial.momentum).len() < 1e-9);
 
-        compare_vec2!(
-            sim.particles[0].pos,
-            Vec2 { x: 5.3, y: 20.2 },
-            "p1 position right before the wall collision",
+        // Incremental stats match the ones computed from scratch
+        let mut sim = Simulation::new(100., 100., 60, None);
+        sim.set_periodic(true, false);
+        sim.set_gravity(0., 20.);
+        sim.set_restitution(0.9);
+        sim.add_segment(&Segment::new(20., 95., 80., 95.).with_velocity(0., -3.));
+        sim.add_particle(&Particle::new(48., 40., 0., 0., 1., 4., None).as_fixed());
+        gas(&mut sim);
+        for i in 0..90 {
+            sim.tick();
+            if i == 30 {
+                let id = sim.particle_ids()[5];
+                sim.remove_particle(&id);
+            }
+        }
+        let incremental = sim.stats();
+        let scratch = Moments::new(sim.t, sim.particles.iter().map(|(_, p)| p)).stats();
+        assert_eq!(incremental.particles, 23);
+        assert!((incremental.kinetic_energy - scratch.kinetic_energy).abs() < 1e-9);
+        assert!((incremental.momentum - scratch.momentum).len() < 1e-9);
+        assert!((incremental.angular_momentum - scratch.angular_momentum).abs() < 1e-8);
+    }
+
+    #[test]
+    fn test_simulation_pressure() {
+        // Particle bouncing between the vertical borders
+        let mut sim = Simulation::new(100., 100., 10, None);
+        sim.set_pressure_window(20.);
+        sim.add_particle(&Particle::new(50., 50., 10., 0., 2., 1., None));
+        sim.advance_to(20.);
+        assert_eq!(sim.get_segment_hits(1) + sim.get_segment_hits(3), 2);
+        assert_eq!(sim.get_segment_hits(0) + sim.get_segment_hits(2), 0);
+        compare_floats!(sim.get_segment_impulse(1) + sim.get_segment_impulse(3), 80.);
+        compare_floats!(sim.get_segments_pressure(vec![1, 3]), 80. / 20. / 200.);
+        compare_floats!(sim.get_domain_pressure(), 80. / 20. / 400.);
+        assert_eq!(sim.get_pressure(0), 0.);
+
+        // Dilute gas obeys P * A = N * T
+        let mut sim = Simulation::new(100., 100., 60, None);
+        sim.set_pressure_window(100.);
+        add_grid_gas(&mut sim, 6, 5, 8., 16., |i, j| 1. + (i + j) as f64 % 3., 1.);
+        sim.advance_to(100.);
+        let stats = sim.stats();
+        let ideal = stats.particles as f64 * stats.temperature / (100. * 100.);
+        let pressure = sim.get_domain_pressure();
+        assert!(
+            (pressure - ideal).abs() < 0.1 * ideal,
+            "{} {}",
+            pressure,
+            ideal
         );
 
-        compare_vec2!(
-            sim.particles[0].v,
-            expected_p1v,
-            "p1 velocity right before the wall collision",
+        // Restored simulation keeps measuring from the checkpoint
+        let checkpoint = sim.checkpoint();
+        let hits = sim.get_segment_hits(1);
+        sim.reset_wall_stats();
+        sim.restore(&checkpoint);
+        assert_eq!(sim.get_segment_hits(1), hits);
+        assert_eq!(sim.get_domain_pressure(), pressure);
+    }
+
+    #[test]
+    fn test_simulation_speed_distribution() {
+        // Particles start with the same speed in different directions
+        let mut sim = Simulation::new(200., 200., 10, None);
+        for i in 0..10 {
+            for j in 0..10 {
+                let angle = (10 * i + j) as f64 * 2.4;
+                sim.add_particle(&Particle::new(
+                    10. + 20. * i as f64,
+                    10. + 20. * j as f64,
+                    10. * angle.cos(),
+                    10. * angle.sin(),
+                    1.,
+                    2.,
+                    None,
+                ));
+            }
+        }
+        sim.add_particle(&Particle::new(100., 100., 0., 0., 1., 3., None).as_fixed());
+
+        // Area between the histogram and the theoretical curve
+        let deviation = |sim: &Simulation| {
+            let histogram = sim.speed_histogram(0., 30., 10);
+            let curve = sim.maxwell_speed_curve(histogram.get_centers());
+            assert_eq!(histogram.get_total(), 100);
+            histogram
+                .get_density()
+                .iter()
+                .zip(curve)
+                .map(|(density, pdf)| (density - pdf).abs() * histogram.get_bin_width())
+                .sum::<f64>()
+        };
+
+        let initial = deviation(&sim);
+        assert_eq!(sim.speed_histogram(0., 30., 10).counts()[3], 100);
+        let vx = sim.velocity_x_histogram(-10., 10., 2);
+        assert_eq!(vx.counts().iter().sum::<u32>() + vx.get_overflow(), 100);
+
+        sim.advance_to(60.);
+        let relaxed = deviation(&sim);
+        assert!(relaxed < 0.5 * initial, "{} {}", relaxed, initial);
+    }
+
+    #[test]
+    fn test_simulation_msd() {
+        // Displacement is unwrapped over the periodic borders
+        let mut sim = Simulation::new(100., 100., 10, None);
+        sim.set_periodic(true, true);
+        sim.add_particle(&Particle::new(90., 50., 10., 5., 1., 1., None));
+        sim.start_msd(0.5);
+        sim.advance_to(3.);
+        let curve = sim.msd_curve().unwrap();
+        assert_eq!(curve.t, vec![0., 0.5, 1., 1.5, 2., 2.5, 3.]);
+        for (t, msd) in curve.t.iter().zip(&curve.msd) {
+            assert!((msd - 125. * t * t).abs() < 1e-9, "{} {}", t, msd);
+        }
+        assert!((sim.get_msd().unwrap() - 125. * 9.).abs() < 1e-9);
+
+        // Light particles diffuse faster than heavy ones
+        let red = Some(RGBA::new(255, 0, 0, None));
+        let blue = Some(RGBA::new(0, 0, 255, None));
+        let mut sim = Simulation::new(100., 100., 10, None);
+        sim.set_periodic(true, true);
+        for i in 0..8 {
+            for j in 0..8 {
+                let heavy = (i + j) % 2 == 1;
+                let angle = (8 * i + j) as f64 * 2.4;
+                let speed = if heavy { 5. } else { 10. };
+                sim.add_particle(&Particle::new(
+                    6. + 12. * i as f64,
+                    6. + 12. * j as f64,
+                    speed * angle.cos(),
+                    speed * angle.sin(),
+                    if heavy { 4. } else { 1. },
+                    2.,
+                    if heavy { blue } else { red },
+                ));
+            }
+        }
+        sim.start_msd(1.);
+        sim.advance_to(60.);
+        let light = sim.get_species_diffusion_coefficient(red, 10.).unwrap();
+        let heavy = sim.get_species_diffusion_coefficient(blue, 10.).unwrap();
+        let all = sim.get_diffusion_coefficient(10.).unwrap();
+        assert!(
+            heavy > 0. && heavy < all && all < light,
+            "{} {} {}",
+            heavy,
+            all,
+            light
         );
-        // Wall collision tick
-        sim.tick();
+        assert_eq!(sim.msd_curve().unwrap().species[0].count, 32);
 
-        compare_vec2!(
-            sim.particles[0].pos,
-            Vec2 {
-                x: 5.8,
-                y: 19.8666666666,
-            },
-            "p1 position right after the wall collision",
+        // Removed particles are not taken into account
+        sim.stop_msd();
+        let id = sim.particle_ids()[0];
+        sim.remove_particle(&id);
+        sim.advance_to(65.);
+        assert_eq!(sim.msd_curve().unwrap().t.len(), 61);
+        assert!(sim.get_msd().unwrap() > 0.);
+    }
+
+    #[test]
+    fn test_simulation_free_paths() {
+        let mut sim = Simulation::new(200., 200., 10, None);
+        sim.set_periodic(true, true);
+        for i in 0..12 {
+            for j in 0..12 {
+                let angle = (12 * i + j) as f64 * 2.4;
+                sim.add_particle(&Particle::new(
+                    8. + 16. * i as f64,
+                    8. + 16. * j as f64,
+                    10. * angle.cos(),
+                    10. * angle.sin(),
+                    1.,
+                    3.,
+                    None,
+                ));
+            }
+        }
+        // Gas comes to equilibrium first
+        sim.advance_to(20.);
+        sim.start_free_paths(200., 20., 20);
+        sim.advance_to(120.);
+
+        let enskog = sim.get_enskog();
+        compare_floats!(enskog.packing_fraction, 144. * PI * 9. / 40000.);
+        let paths = sim.get_free_paths().unwrap();
+        assert!(paths.get_count() > 1000);
+        let path = sim.get_mean_free_path().unwrap();
+        let time = sim.get_mean_free_time().unwrap();
+        assert!(
+            (path / enskog.mean_free_path - 1.).abs() < 0.05,
+            "{} {:?}",
+            path,
+            enskog
         );
-        compare_vec2!(
-            sim.particles[0].v,
-            Vec2 {
-                x: 50.,
-                y: -33.3333333333,
-            },
-            "p1 velocity right after the wall collision",
+        assert!(
+            (time / enskog.mean_free_time - 1.).abs() < 0.05,
+            "{} {:?}",
+            time,
+            enskog
+        );
+        assert_eq!(
+            paths.paths().counts().iter().sum::<u32>() + paths.paths().get_overflow(),
+            paths.get_count() as u32
+        );
+    }
+
+    #[test]
+    fn test_simulation_free_paths_obstacles() {
+        // The first ball stops, passing its velocity to the second one,
+        // which bounces off the fixed disk and comes back at t = 9
+        let mut sim = Simulation::new(100., 100., 10, None);
+        sim.set_exact_events(true);
+        sim.add_particle(&Particle::new(20., 50., 10., 0., 1., 5., None));
+        sim.add_particle(&Particle::new(40., 50., 0., 0., 1., 5., None));
+        sim.add_particle(&Particle::new(90., 50., 0., 0., 1., 5., None).as_fixed());
+        sim.start_free_paths(100., 10., 10);
+        sim.advance_to(9.5);
+
+        // Both flights go from t = 1 to t = 9 through the bounce
+        let paths = sim.get_free_paths().unwrap();
+        assert_eq!(paths.get_count(), 2);
+        assert!((paths.get_mean_free_time().unwrap() - 8.).abs() < 1e-6);
+        assert!((paths.get_mean_free_path().unwrap() - 40.).abs() < 1e-6);
+        assert_eq!(paths.paths().counts(), &[1, 0, 0, 0, 0, 0, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_simulation_rdf() {
+        // Dense gas of disks with walls
+        let mut sim = Simulation::new(100., 100., 10, None);
+        for i in 0..12 {
+            for j in 0..12 {
+                let angle = (12 * i + j) as f64 * 2.4;
+                sim.add_particle(&Particle::new(
+                    5. + 8.2 * i as f64,
+                    5. + 8.2 * j as f64,
+                    10. * angle.cos(),
+                    10. * angle.sin(),
+                    1.,
+                    3.,
+                    None,
+                ));
+            }
+        }
+        sim.advance_to(20.);
+        sim.start_rdf(30., 30, 0.5);
+        sim.advance_to(70.);
+
+        let rdf = sim.get_rdf().unwrap();
+        assert_eq!(rdf.get_samples(), 101);
+        let g = rdf.get_values();
+        // Disks can't be closer than the diameter
+        assert!(g[..6].iter().all(|&g| g == 0.), "{:?}", g);
+        // Neighbours are piled up at the contact
+        assert!(g[6] > 1.5 && g[6] > g[7], "{:?}", g);
+        // And there is no order far away
+        for g in &g[20..] {
+            assert!((g - 1.).abs() < 0.15, "{:?}", g);
+        }
+    }
+
+    #[test]
+    fn test_simulation_probes() {
+        // Particles pass between the halves only through the gate
+        let mut sim = Simulation::new(100., 100., 10, None);
+        sim.set_gravity(0., 5.);
+        for i in 0..6 {
+            for j in 0..6 {
+                let angle = (6 * i + j) as f64 * 2.4;
+                sim.add_particle(&Particle::new(
+                    8. + 7. * i as f64,
+                    10. + 15. * j as f64,
+                    20. * angle.cos(),
+                    20. * angle.sin(),
+                    1.,
+                    2.,
+                    None,
+                ));
+            }
+        }
+        let right = Region::rect(50., 0., 50., 100.);
+        assert_eq!(sim.get_region_stats(&right).particles, 0);
+        let gate = sim.add_gate(&Segment::new(50., 0., 50., 100.));
+        for _ in 0..100 {
+            sim.tick();
+            let crossed = sim.get_gate(gate).unwrap();
+            let stats = sim.get_region_stats(&right);
+            assert_eq!(crossed.forward - crossed.backward, stats.particles as u64);
+        }
+        let crossed = sim.get_gate(gate).unwrap();
+        assert!(crossed.backward > 0);
+        compare_floats!(
+            sim.get_gate_flux(gate).unwrap(),
+            sim.get_region_stats(&right).particles as f64 / 10.
         );
+
+        let stats = sim.get_region_stats(&Region::rect(0., 0., 100., 100.));
+        assert_eq!(stats.particles, 36);
+        compare_floats!(stats.density, 36. / 10000.);
+        compare_floats!(stats.mean_kinetic_energy, sim.stats().temperature);
+
+        // Predictions made before the particle is wrapped don't count
+        let mut sim = Simulation::new(100., 100., 10, None);
+        sim.set_periodic(true, false);
+        sim.add_particle(&Particle::new(10., 50., 10., 0., 1., 2., None));
+        let gate = sim.add_gate(&Segment::new(50., 40., 50., 60.));
+        sim.advance_to(20.);
+        let crossed = sim.get_gate(gate).unwrap();
+        assert_eq!((crossed.forward, crossed.backward), (2, 0));
+        sim.reset_gates();
+        assert_eq!(sim.get_gate(gate).unwrap().forward, 0);
+
+        // Gate events of the checkpoint aren't doubled by restore
+        let mut sim = Simulation::new(100., 100., 10, None);
+        sim.add_particle(&Particle::new(10., 50., 10., 0., 1., 2., None));
+        let gate = sim.add_gate(&Segment::new(50., 40., 50., 60.));
+        sim.advance_to(1.);
+        let checkpoint = sim.checkpoint();
+        sim.restore(&checkpoint);
+        sim.advance_to(6.);
+        let crossed = sim.get_gate(gate).unwrap();
+        assert_eq!((crossed.forward, crossed.backward), (1, 0));
+    }
+
+    #[test]
+    fn test_simulation_thermal_walls() {
+        // Hot left and cold right walls
+        let build = || {
+            let mut scene = Scene::new(60., 60.);
+            scene.border_temperatures = [None, Some(25.), None, Some(100.)];
+            for i in 0..5 {
+                for j in 0..5 {
+                    let angle = (5 * i + j) as f64 * 2.4;
+                    scene.particles.push(Particle::new(
+                        6. + 12. * i as f64,
+                        6. + 12. * j as f64,
+                        5. * angle.cos(),
+                        5. * angle.sin(),
+                        1.,
+                        1.,
+                        None,
+                    ));
+                }
+            }
+            Simulation::from_scene(&scene).unwrap()
+        };
+        let energy = |sim: &Simulation| -> f64 {
+            sim.particles
+                .iter()
+                .map(|(_, p)| 0.5 * p.m * p.v.len_sqr())
+                .sum()
+        };
+
+        let mut sim = build();
+        assert_eq!(sim.to_scene().border_temperatures[3], Some(100.));
+        let initial_energy = energy(&sim);
+        sim.advance_to(20.);
+        let heat: f64 = (0..4).map(|s| sim.get_segment_heat(s)).sum();
+        assert!((energy(&sim) - initial_energy - heat).abs() < 1e-6);
+        // Elastic walls don't change the energy
+        assert!(sim.get_segment_heat(0).abs() < 1e-9);
+        let temperature = sim.stats().temperature;
+        assert!(25. < temperature && temperature < 100., "{}", temperature);
+
+        // Heat flows through the gas from the hot wall to the cold one
+        sim.reset_wall_stats();
+        sim.advance_to(60.);
+        let (hot, cold) = (sim.get_heat_flow(3), sim.get_heat_flow(1));
+        assert!(hot > 0. && cold < 0., "{} {}", hot, cold);
+        assert!((hot + cold).abs() < 0.5 * hot, "{} {}", hot, cold);
+
+        // The same seed gives the same run, checkpoint keeps the random state
+        let mut other = build();
+        other.advance_to(20.);
+        let checkpoint = other.checkpoint();
+        other.advance_to(60.);
+        let state =
+            |sim: &Simulation| -> Vec<Particle> { sim.particles.iter().map(|(_, p)| *p).collect() };
+        assert_eq!(state(&other), state(&sim));
+        let mut restored = Simulation::from_checkpoint(&checkpoint);
+        restored.advance_to(60.);
+        assert_eq!(state(&restored), state(&sim));
+
+        let mut reseeded = build();
+        reseeded.set_seed(7);
+        reseeded.advance_to(20.);
+        assert_ne!(reseeded.get_segment_heat(3), other.get_segment_heat(3));
+
+        // Turned off thermal wall reflects particles again
+        sim.set_segment_temperature(3, None);
+        sim.set_segment_temperature(1, None);
+        sim.reset_wall_stats();
+        let energy_before = energy(&sim);
+        sim.advance_to(70.);
+        assert!((energy(&sim) - energy_before).abs() < 1e-6);
     }
 }
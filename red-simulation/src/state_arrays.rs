@@ -0,0 +1,81 @@
+use crate::particle::ParticleStore;
+
+// Copy of particles' state in the struct-of-arrays form, so javascript
+// can read it through typed array views without any serialization.
+// Arrays are indexed by particle slots, empty slots are zeroed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StateArrays {
+    pub x: Vec<f64>,
+    pub y: Vec<f64>,
+    pub vx: Vec<f64>,
+    pub vy: Vec<f64>,
+    pub r: Vec<f64>,
+    pub m: Vec<f64>,
+    // 1 for the living particle, 0 for the empty slot
+    pub alive: Vec<u8>,
+}
+
+impl StateArrays {
+    pub fn update(&mut self, particles: &ParticleStore) {
+        let slots = particles.slots();
+        for array in [
+            &mut self.x,
+            &mut self.y,
+            &mut self.vx,
+            &mut self.vy,
+            &mut self.r,
+            &mut self.m,
+        ] {
+            array.clear();
+            array.resize(slots, 0.);
+        }
+        self.alive.clear();
+        self.alive.resize(slots, 0);
+
+        for (i, p) in particles.iter() {
+            self.x[i] = p.pos.x;
+            self.y[i] = p.pos.y;
+            self.vx[i] = p.v.x;
+            self.vy[i] = p.v.y;
+            self.r[i] = p.r;
+            self.m[i] = p.m;
+            self.alive[i] = 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.alive.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.alive.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::particle::Particle;
+
+    #[test]
+    fn test_state_arrays() {
+        let mut store = ParticleStore::new();
+        let first = store.insert(Particle::new(1., 2., 3., 4., 5., 6., None));
+        store.insert(Particle::new(7., 8., 9., 10., 11., 12., None));
+
+        let mut arrays = StateArrays::default();
+        arrays.update(&store);
+        assert_eq!(arrays.x, vec![1., 7.]);
+        assert_eq!(arrays.vy, vec![4., 10.]);
+        assert_eq!(arrays.m, vec![5., 11.]);
+        assert_eq!(arrays.alive, vec![1, 1]);
+
+        // Removed particle leaves the zeroed slot
+        store.remove(first);
+        arrays.update(&store);
+        assert_eq!(arrays.len(), 2);
+        assert_eq!(arrays.x, vec![0., 7.]);
+        assert_eq!(arrays.r, vec![0., 12.]);
+        assert_eq!(arrays.alive, vec![0, 1]);
+    }
+}
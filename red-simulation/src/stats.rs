@@ -0,0 +1,169 @@
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::geom::Vec2;
+use crate::particle::Particle;
+
+// Thermodynamic observables of the moving particles,
+// fixed particles are not taken into account.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    pub particles: usize,
+    pub kinetic_energy: f64,
+    // 2D temperature, that is the mean kinetic energy
+    // per particle with Boltzmann constant equal to 1
+    pub temperature: f64,
+    pub momentum: Vec2,
+    // About the origin of coordinates
+    pub angular_momentum: f64,
+}
+
+// Sums over the moving particles at the moment `t`. Under uniform
+// acceleration they evolve analytically, so the sums are updated
+// only when particles collide, cross the periodic border, appear
+// or disappear.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Moments {
+    t: f64,
+    count: usize,
+    mass: f64,
+    // Sum of m * r
+    mass_pos: Vec2,
+    momentum: Vec2,
+    kinetic_energy: f64,
+    angular_momentum: f64,
+}
+
+impl Moments {
+    pub(crate) fn new<'a, I: Iterator<Item = &'a Particle>>(t: f64, particles: I) -> Moments {
+        let mut moments = Moments {
+            t,
+            ..Default::default()
+        };
+        for particle in particles {
+            moments.add(particle);
+        }
+        moments
+    }
+
+    // Moments at the moment `t` after the free flight with acceleration `a`.
+    pub(crate) fn at(&self, t: f64, a: Vec2) -> Moments {
+        let dt = t - self.t;
+        if dt == 0. || a.is_zero() {
+            return Moments { t, ..*self };
+        }
+        Moments {
+            t,
+            count: self.count,
+            mass: self.mass,
+            mass_pos: self.mass_pos + self.momentum * dt + a * (0.5 * self.mass * dt * dt),
+            momentum: self.momentum + a * (self.mass * dt),
+            kinetic_energy: self.kinetic_energy
+                + self.momentum * a * dt
+                + 0.5 * self.mass * a.len_sqr() * dt * dt,
+            angular_momentum: self.angular_momentum
+                + self.mass_pos.cross(&a) * dt
+                + 0.5 * self.momentum.cross(&a) * dt * dt,
+        }
+    }
+
+    // Replaces the particle's state at the moment `t`. `None` stands
+    // for the particle that has just appeared or disappeared.
+    pub(crate) fn update(
+        &mut self,
+        t: f64,
+        a: Vec2,
+        old: Option<&Particle>,
+        new: Option<&Particle>,
+    ) {
+        *self = self.at(t, a);
+        if let Some(old) = old {
+            self.sub(old);
+        }
+        if let Some(new) = new {
+            self.add(new);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> Stats {
+        Stats {
+            particles: self.count,
+            kinetic_energy: self.kinetic_energy,
+            temperature: if self.count > 0 {
+                self.kinetic_energy / self.count as f64
+            } else {
+                0.
+            },
+            momentum: self.momentum,
+            angular_momentum: self.angular_momentum,
+        }
+    }
+
+    fn add(&mut self, p: &Particle) {
+        if !p.fixed {
+            self.count += 1;
+            self.accumulate(p, 1.);
+        }
+    }
+
+    fn sub(&mut self, p: &Particle) {
+        if !p.fixed {
+            self.count -= 1;
+            self.accumulate(p, -1.);
+        }
+    }
+
+    fn accumulate(&mut self, p: &Particle, sign: f64) {
+        let m = p.m * sign;
+        self.mass += m;
+        self.mass_pos += p.pos * m;
+        self.momentum += p.v * m;
+        self.kinetic_energy += 0.5 * m * p.v.len_sqr();
+        self.angular_momentum += p.pos.cross(&p.v) * m;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compare_floats, compare_vec2};
+
+    #[test]
+    fn test_moments() {
+        let particles = [
+            Particle::new(1., 2., 3., -1., 2., 1., None),
+            Particle::new(5., 1., -2., 4., 1., 1., None),
+            Particle::new(3., 3., 0., 0., 1., 1., None).as_fixed(),
+        ];
+        let stats = Moments::new(0., particles.iter()).stats();
+        assert_eq!(stats.particles, 2);
+        compare_floats!(stats.kinetic_energy, 20.);
+        compare_floats!(stats.temperature, 10.);
+        compare_vec2!(stats.momentum, Vec2 { x: 4., y: 2. }, "momentum");
+        compare_floats!(stats.angular_momentum, -14. + 22.);
+
+        // Free flight under gravity matches the moved particles
+        let g = Vec2 { x: 1., y: -3. };
+        let moved: Vec<Particle> = particles
+            .iter()
+            .map(|p| {
+                let mut p = *p;
+                if !p.fixed {
+                    p.mv_accelerated(1.5, g);
+                }
+                p
+            })
+            .collect();
+        let evolved = Moments::new(0., particles.iter()).at(1.5, g).stats();
+        let expected = Moments::new(1.5, moved.iter()).stats();
+        compare_floats!(evolved.kinetic_energy, expected.kinetic_energy);
+        compare_vec2!(evolved.momentum, expected.momentum, "momentum");
+        compare_floats!(evolved.angular_momentum, expected.angular_momentum);
+
+        let mut moments = Moments::new(0., particles.iter());
+        moments.update(0., g, Some(&particles[0]), None);
+        assert_eq!(moments.stats().particles, 1);
+        compare_floats!(moments.stats().kinetic_energy, 10.);
+    }
+}
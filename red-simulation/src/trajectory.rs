@@ -0,0 +1,500 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::geom::Vec2;
+use crate::particle::{Particle, ParticleId, RGBA};
+
+// Binary trajectory starts with these bytes and the format version.
+pub const TRAJECTORY_MAGIC: &[u8; 4] = b"REDT";
+pub const TRAJECTORY_VERSION: u32 = 1;
+
+// State of a single particle at the moment of the frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParticleState {
+    pub id: ParticleId,
+    pub pos: Vec2,
+    pub v: Vec2,
+    pub r: f64,
+    pub m: f64,
+    pub color: Option<RGBA>,
+}
+
+impl ParticleState {
+    pub fn new(id: ParticleId, p: &Particle) -> ParticleState {
+        ParticleState {
+            id,
+            pos: p.pos,
+            v: p.v,
+            r: p.r,
+            m: p.m,
+            color: p.color,
+        }
+    }
+}
+
+// All the living particles at the moment `t`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub t: f64,
+    pub particles: Vec<ParticleState>,
+}
+
+// Domain of the recorded simulation, it's needed to unwrap
+// positions and to set up the box in visualization tools.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrajectoryHeader {
+    pub width: f64,
+    pub height: f64,
+    pub periodic_x: bool,
+    pub periodic_y: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trajectory {
+    pub header: TrajectoryHeader,
+    pub frames: Vec<Frame>,
+}
+
+#[derive(Debug)]
+pub enum TrajectoryError {
+    Io(io::Error),
+    // Data doesn't start with `TRAJECTORY_MAGIC`
+    InvalidMagic,
+    UnsupportedVersion(u32),
+    // Data ends in the middle of the frame
+    Truncated,
+}
+
+impl fmt::Display for TrajectoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "can't read the trajectory: {}", e),
+            Self::InvalidMagic => write!(f, "data is not a trajectory"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "trajectory version {} is not supported, expected {}",
+                version, TRAJECTORY_VERSION
+            ),
+            Self::Truncated => write!(f, "trajectory is truncated"),
+        }
+    }
+}
+
+impl std::error::Error for TrajectoryError {}
+
+impl From<io::Error> for TrajectoryError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            TrajectoryError::Truncated
+        } else {
+            TrajectoryError::Io(e)
+        }
+    }
+}
+
+impl Trajectory {
+    pub fn new(header: TrajectoryHeader) -> Trajectory {
+        Trajectory {
+            header,
+            frames: Vec::new(),
+        }
+    }
+
+    // One row per particle per frame:
+    // t,index,generation,x,y,vx,vy,r,m,color
+    pub fn write_csv<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "t,index,generation,x,y,vx,vy,r,m,color")?;
+        for frame in &self.frames {
+            for p in &frame.particles {
+                writeln!(
+                    w,
+                    "{},{},{},{},{},{},{},{},{},{}",
+                    frame.t,
+                    p.id.index(),
+                    p.id.generation(),
+                    p.pos.x,
+                    p.pos.y,
+                    p.v.x,
+                    p.v.y,
+                    p.r,
+                    p.m,
+                    p.color.map(|c| c.as_css_hex()).unwrap_or_default()
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // Extended XYZ, that is understood by OVITO and VMD. Tools expect
+    // 3D data, so z is always 0 and the box is 1 unit thick.
+    // Particles are named by their color, `P` is used for uncolored ones.
+    pub fn write_xyz<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let h = &self.header;
+        let pbc = |periodic: bool| if periodic { "T" } else { "F" };
+
+        for frame in &self.frames {
+            writeln!(w, "{}", frame.particles.len())?;
+            writeln!(
+                w,
+                "Lattice=\"{} 0 0 0 {} 0 0 0 1\" \
+                 Properties=species:S:1:id:I:1:pos:R:3:velo:R:3:radius:R:1:mass:R:1 \
+                 Time={} pbc=\"{} {} F\"",
+                h.width,
+                h.height,
+                frame.t,
+                pbc(h.periodic_x),
+                pbc(h.periodic_y)
+            )?;
+            for p in &frame.particles {
+                writeln!(
+                    w,
+                    "{} {} {} {} 0 {} {} 0 {} {}",
+                    p.color
+                        .map(|c| c.as_css_hex())
+                        .unwrap_or_else(|| "P".to_owned()),
+                    p.id.index(),
+                    p.pos.x,
+                    p.pos.y,
+                    p.v.x,
+                    p.v.y,
+                    p.r,
+                    p.m
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // Little-endian binary format:
+    //   header: magic, version u32, width f64, height f64,
+    //           flags u8 (bit 0 - periodic x, bit 1 - periodic y)
+    //   frame:  t f64, particles count u32, particles
+    //   particle: index u32, generation u32, x, y, vx, vy, r, m f64,
+    //             has color u8, rgba 4 x u8 if it has color
+    pub fn write_binary<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let h = &self.header;
+        w.write_all(TRAJECTORY_MAGIC)?;
+        w.write_all(&TRAJECTORY_VERSION.to_le_bytes())?;
+        w.write_all(&h.width.to_le_bytes())?;
+        w.write_all(&h.height.to_le_bytes())?;
+        w.write_all(&[h.periodic_x as u8 | (h.periodic_y as u8) << 1])?;
+
+        for frame in &self.frames {
+            w.write_all(&frame.t.to_le_bytes())?;
+            w.write_all(&(frame.particles.len() as u32).to_le_bytes())?;
+            for p in &frame.particles {
+                w.write_all(&(p.id.index() as u32).to_le_bytes())?;
+                w.write_all(&p.id.generation().to_le_bytes())?;
+                for value in &[p.pos.x, p.pos.y, p.v.x, p.v.y, p.r, p.m] {
+                    w.write_all(&value.to_le_bytes())?;
+                }
+                match p.color {
+                    Some(c) => w.write_all(&[1, c.red, c.green, c.blue, c.alpha])?,
+                    None => w.write_all(&[0])?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut out = Vec::new();
+        self.write_csv(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    pub fn to_xyz(&self) -> String {
+        let mut out = Vec::new();
+        self.write_xyz(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write_binary(&mut out).unwrap();
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Trajectory, TrajectoryError> {
+        let reader = TrajectoryReader::new(data)?;
+        let header = reader.header();
+        Ok(Trajectory {
+            header,
+            frames: reader.collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+// Reads the binary trajectory frame by frame,
+// so long runs don't have to fit into memory.
+pub struct TrajectoryReader<R: Read> {
+    reader: R,
+    header: TrajectoryHeader,
+    done: bool,
+}
+
+impl<R: Read> TrajectoryReader<R> {
+    pub fn new(mut reader: R) -> Result<TrajectoryReader<R>, TrajectoryError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != TRAJECTORY_MAGIC {
+            return Err(TrajectoryError::InvalidMagic);
+        }
+        let version = read_u32(&mut reader)?;
+        if version != TRAJECTORY_VERSION {
+            return Err(TrajectoryError::UnsupportedVersion(version));
+        }
+
+        let width = read_f64(&mut reader)?;
+        let height = read_f64(&mut reader)?;
+        let flags = read_u8(&mut reader)?;
+        Ok(TrajectoryReader {
+            reader,
+            header: TrajectoryHeader {
+                width,
+                height,
+                periodic_x: flags & 1 != 0,
+                periodic_y: flags & 2 != 0,
+            },
+            done: false,
+        })
+    }
+
+    pub fn header(&self) -> TrajectoryHeader {
+        self.header
+    }
+
+    fn read_frame(&mut self, t: f64) -> Result<Frame, TrajectoryError> {
+        let r = &mut self.reader;
+        let count = read_u32(r)?;
+        // The count comes from the file, a corrupted one
+        // mustn't reserve gigabytes before the reading fails
+        let mut particles = Vec::with_capacity(count.min(4096) as usize);
+
+        for _ in 0..count {
+            let index = read_u32(r)? as usize;
+            let generation = read_u32(r)?;
+            let mut values = [0.; 6];
+            for value in values.iter_mut() {
+                *value = read_f64(r)?;
+            }
+            let color = if read_u8(r)? != 0 {
+                let mut rgba = [0; 4];
+                r.read_exact(&mut rgba)?;
+                Some(RGBA {
+                    red: rgba[0],
+                    green: rgba[1],
+                    blue: rgba[2],
+                    alpha: rgba[3],
+                })
+            } else {
+                None
+            };
+
+            particles.push(ParticleState {
+                id: ParticleId::new(index, generation),
+                pos: Vec2 {
+                    x: values[0],
+                    y: values[1],
+                },
+                v: Vec2 {
+                    x: values[2],
+                    y: values[3],
+                },
+                r: values[4],
+                m: values[5],
+                color,
+            });
+        }
+        Ok(Frame { t, particles })
+    }
+}
+
+impl<R: Read> Iterator for TrajectoryReader<R> {
+    type Item = Result<Frame, TrajectoryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        // The data may end only between frames
+        let mut t = [0; 8];
+        let frame = match read_or_eof(&mut self.reader, &mut t) {
+            Ok(true) => self.read_frame(f64::from_le_bytes(t)),
+            Ok(false) => {
+                self.done = true;
+                return None;
+            }
+            Err(e) => Err(e),
+        };
+        if frame.is_err() {
+            self.done = true;
+        }
+        Some(frame)
+    }
+}
+
+// Fills the whole buffer, returns `false` if there is no data at all.
+fn read_or_eof<R: Read>(r: &mut R, buf: &mut [u8]) -> Result<bool, TrajectoryError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match r.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(TrajectoryError::Truncated),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_f64<R: Read>(r: &mut R) -> io::Result<f64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(f64::from_le_bytes(buf))
+}
+
+// Samples the simulation every `interval` starting from `start`.
+// Moments are computed from the sample number, so they don't drift.
+#[derive(Debug, Clone)]
+pub(crate) struct TrajectoryRecorder {
+    start: f64,
+    interval: f64,
+    samples: u64,
+    pub(crate) active: bool,
+    pub(crate) trajectory: Trajectory,
+}
+
+impl TrajectoryRecorder {
+    pub(crate) fn new(start: f64, interval: f64, header: TrajectoryHeader) -> TrajectoryRecorder {
+        TrajectoryRecorder {
+            start,
+            interval,
+            samples: 0,
+            active: true,
+            trajectory: Trajectory::new(header),
+        }
+    }
+
+    // Moment of the next sample, if it's due not later than `t`.
+    pub(crate) fn next_sample(&self, t: f64) -> Option<f64> {
+        let next = self.next_t();
+        if self.active && next <= t {
+            Some(next)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn record(&mut self, frame: Frame) {
+        self.trajectory.frames.push(frame);
+        self.samples += 1;
+    }
+
+    // Skips samples in the past, e.g. after the checkpoint is restored.
+    pub(crate) fn skip_to(&mut self, t: f64) {
+        while self.next_t() < t {
+            self.samples += 1;
+        }
+    }
+
+    fn next_t(&self) -> f64 {
+        self.start + self.samples as f64 * self.interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trajectory() -> Trajectory {
+        let red = RGBA::new(255, 0, 0, None);
+        let mut trajectory = Trajectory::new(TrajectoryHeader {
+            width: 100.,
+            height: 50.,
+            periodic_x: true,
+            periodic_y: false,
+        });
+        for (i, t) in [0., 0.5].iter().enumerate() {
+            let shift = i as f64;
+            trajectory.frames.push(Frame {
+                t: *t,
+                particles: vec![
+                    ParticleState::new(
+                        ParticleId::new(0, 0),
+                        &Particle::new(10. + shift, 20., 2., 0., 1., 5., None),
+                    ),
+                    ParticleState::new(
+                        ParticleId::new(2, 1),
+                        &Particle::new(40., 30.5, 0., -1., 2., 3., Some(red)),
+                    ),
+                ],
+            });
+        }
+        trajectory
+    }
+
+    #[test]
+    fn test_trajectory_text() {
+        let csv = trajectory().to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "t,index,generation,x,y,vx,vy,r,m,color");
+        assert_eq!(lines[1], "0,0,0,10,20,2,0,5,1,");
+        assert_eq!(lines[4], "0.5,2,1,40,30.5,0,-1,3,2,#FF0000FF");
+
+        let xyz = trajectory().to_xyz();
+        let lines: Vec<&str> = xyz.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[0], "2");
+        assert!(lines[1].starts_with("Lattice=\"100 0 0 0 50 0 0 0 1\" Properties="));
+        assert!(lines[5].contains("Time=0.5 pbc=\"T F F\""));
+        assert_eq!(lines[6], "P 0 11 20 0 2 0 0 5 1");
+        assert_eq!(lines[7], "#FF0000FF 2 40 30.5 0 0 -1 0 3 2");
+    }
+
+    #[test]
+    fn test_trajectory_binary() {
+        let trajectory = trajectory();
+        let data = trajectory.to_bytes();
+        assert_eq!(Trajectory::from_bytes(&data).unwrap(), trajectory);
+
+        // Frames are read one by one
+        let mut reader = TrajectoryReader::new(&data[..]).unwrap();
+        assert_eq!(reader.header(), trajectory.header);
+        assert_eq!(reader.next().unwrap().unwrap().t, 0.);
+        assert_eq!(reader.next().unwrap().unwrap().t, 0.5);
+        assert!(reader.next().is_none());
+
+        assert!(matches!(
+            Trajectory::from_bytes(&data[..data.len() - 3]),
+            Err(TrajectoryError::Truncated)
+        ));
+        assert!(matches!(
+            Trajectory::from_bytes(b"RIFF0000"),
+            Err(TrajectoryError::InvalidMagic)
+        ));
+        let mut other_version = data.clone();
+        other_version[4] = 2;
+        assert_eq!(
+            Trajectory::from_bytes(&other_version)
+                .unwrap_err()
+                .to_string(),
+            "trajectory version 2 is not supported, expected 1"
+        );
+    }
+}
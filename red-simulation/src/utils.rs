@@ -1,3 +1,5 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
@@ -15,9 +17,28 @@ pub fn set_panic_hook() {
 
 #[macro_export]
 macro_rules! log {
-    ( $( $t:tt )* ) => {
-        web_sys::console::log_1(&format!( $( $t )* ).into())
-    }
+    ( $( $t:tt )* ) => {{
+        // Console is available only in the browser,
+        // native builds (the render binary, tests) use stderr
+        #[cfg(target_arch = "wasm32")]
+        web_sys::console::log_1(&format!( $( $t )* ).into());
+        #[cfg(not(target_arch = "wasm32"))]
+        eprintln!( $( $t )* );
+    }};
+}
+
+// Values pass to and from js as JSON, like the deprecated
+// `JsValue::from_serde` and `JsValue::into_serde` did.
+pub fn to_js_value<T: Serialize + ?Sized>(value: &T) -> JsValue {
+    js_sys::JSON::parse(&serde_json::to_string(value).unwrap()).unwrap()
+}
+
+pub fn from_js_value<T: DeserializeOwned>(value: &JsValue) -> serde_json::Result<T> {
+    let json = js_sys::JSON::stringify(value)
+        .ok()
+        .and_then(|json| json.as_string())
+        .unwrap_or_default();
+    serde_json::from_str(&json)
 }
 
 pub const EPS: f64 = 1e-10;
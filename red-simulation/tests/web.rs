@@ -11,3 +11,18 @@ wasm_bindgen_test_configure!(run_in_browser);
 fn pass() {
     assert_eq!(1 + 1, 2);
 }
+
+#[wasm_bindgen_test]
+fn state_arrays() {
+    use red_simulation::particle::Particle;
+    use red_simulation::simulation::Simulation;
+
+    let mut sim = Simulation::new(100., 100., 60, None);
+    sim.add_particle(&Particle::new(10., 20., 1., 2., 3., 4., None));
+    sim.add_particle(&Particle::new(50., 60., 5., 6., 7., 8., None));
+
+    assert_eq!(sim.update_state_arrays(), 2);
+    assert_eq!(sim.get_x_array().to_vec(), vec![10., 50.]);
+    assert_eq!(sim.get_vy_array().to_vec(), vec![2., 6.]);
+    assert_eq!(sim.get_alive_array().to_vec(), vec![1, 1]);
+}